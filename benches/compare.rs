@@ -0,0 +1,83 @@
+/* Copyright 2016 Torbjørn Birch Moltu
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+// Compares Eq, Ord and Hash of Nbstr with doing the same on the strs they deref to,
+//  for every layout:
+//     cargo bench
+
+extern crate nbstr;
+use nbstr::NbstrIn;
+use nbstr::layouts::{Layout,Wide,NoGiants,InlineN};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash,Hasher};
+use std::hint::black_box;
+use std::time::Instant;
+
+const ROUNDS: usize = 10_000_000;
+
+fn time<F:FnMut()->u64>(name: &str,  mut f: F) -> f64 {
+    let start = Instant::now();
+    let mut sum = 0u64;
+    for _ in 0..ROUNDS {
+        sum = sum.wrapping_add(f());
+    }
+    black_box(sum);
+    let ns = start.elapsed().as_secs_f64() * 1e9 / ROUNDS as f64;
+    println!("{:40} {:6.2} ns", name, ns);
+    ns
+}
+
+fn compare<L: Layout>(what: &str,  a: &NbstrIn<L>,  b: &NbstrIn<L>) {
+    // black_box every round, so that nothing is computed once outside the loop
+    let eq = time(&format!("{} eq", what), || (black_box(a) == black_box(b)) as u64 );
+    let str_eq = time(&format!("{} eq via str", what), || (**black_box(a) == **black_box(b)) as u64 );
+    let cmp = time(&format!("{} cmp", what), || black_box(a).cmp(black_box(b)) as u64 );
+    let str_cmp = time(&format!("{} cmp via str", what), || (**black_box(a)).cmp(&**black_box(b)) as u64 );
+    let hash = time(&format!("{} hash", what), || {
+        let mut h = DefaultHasher::new();
+        black_box(a).hash(&mut h);
+        h.finish()
+    });
+    let str_hash = time(&format!("{} hash via str", what), || {
+        let mut h = DefaultHasher::new();
+        (**black_box(a)).hash(&mut h);
+        h.finish()
+    });
+    println!("{}: eq {:.2}x, cmp {:.2}x, hash {:.2}x faster\n",
+        what, str_eq/eq, str_cmp/cmp, str_hash/hash
+    );
+}
+
+fn compare_layout<L: Layout>(name: &str) {
+    println!("{} is {} bytes and stores up to {} bytes inline\n",
+        name, NbstrIn::<L>::SIZE, NbstrIn::<L>::MAX_STACK
+    );
+    let short = "qualified";
+    compare("equal inline", &NbstrIn::<L>::from_str(short), &NbstrIn::<L>::from_str(short));
+    compare("different inline", &NbstrIn::<L>::from_str(short), &NbstrIn::<L>::from_str("qualifies"));
+    let long = "a literal that is too long to be stored inline";
+    compare("same literal", &NbstrIn::<L>::from(long), &NbstrIn::<L>::from(long));
+    compare("equal boxed", &NbstrIn::<L>::from(long.to_string()), &NbstrIn::<L>::from(long.to_string()));
+}
+
+fn main() {
+    compare_layout::<Wide>("Wide");
+    compare_layout::<NoGiants>("NoGiants");
+    #[cfg(target_pointer_width="64")]
+    compare_layout::<nbstr::layouts::Len32>("Len32");
+    #[cfg(target_arch="x86_64")]
+    compare_layout::<nbstr::layouts::Packed48>("Packed48");
+    compare_layout::<InlineN<32>>("InlineN<32>");
+}
@@ -0,0 +1,123 @@
+/* Copyright 2016 Torbjørn Birch Moltu
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use Nbstr;
+use shared::{try_stack,from_vec};
+use core::{cmp,fmt};
+use core::str as Str;
+use alloc::vec::Vec;
+
+
+const MAX_STACK_USIZE: usize = Nbstr::MAX_STACK;
+
+/// Builds a Nbstr piece by piece, without going through a `String`.
+///
+/// Content is written into an inline buffer until it no longer fits,
+/// and `finish()` then stores it in the struct itself or an exactly-sized box.
+#[derive(Clone, Default)]
+pub struct NbstrBuilder {
+    // Is used until heap has allocated.
+    stack: [u8; MAX_STACK_USIZE],
+    stack_len: u8,
+    heap: Vec<u8>,
+}
+
+impl NbstrBuilder {
+    /// Create an empty builder that doesn't allocate until it needs to.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Create an empty builder that can hold at least `capacity` bytes without reallocating.
+    ///
+    /// If you know the exact length, this avoids shrinking the allocation in `finish()`.
+    pub fn with_capacity(capacity: usize) -> Self {
+        let mut b = Self::default();
+        if capacity > MAX_STACK_USIZE {
+            b.heap.reserve_exact(capacity);
+        }
+        return b;
+    }
+
+    fn spilled(&self) -> bool {
+        self.heap.capacity() != 0
+    }
+
+    /// Append a str.
+    pub fn push_str(&mut self,  s: &str) {
+        if self.spilled() {
+            self.heap.extend_from_slice(s.as_bytes());
+            return;
+        }
+        let start = self.stack_len as usize;
+        if start + s.len() <= MAX_STACK_USIZE {
+            self.stack[start..start+s.len()].copy_from_slice(s.as_bytes());
+            self.stack_len += s.len() as u8;
+        } else {
+            // Leave room to grow a bit, like String does.
+            self.heap.reserve(cmp::max(start+s.len(), 2*MAX_STACK_USIZE));
+            self.heap.extend_from_slice(&self.stack[..start]);
+            self.heap.extend_from_slice(s.as_bytes());
+        }
+    }
+    /// Append a char.
+    pub fn push(&mut self,  c: char) {
+        self.push_str(c.encode_utf8(&mut [0; 4]));
+    }
+
+    /// Get what has been written so far.
+    pub fn as_str(&self) -> &str {
+        let bytes = if self.spilled() {&self.heap[..]}
+                    else {&self.stack[..self.stack_len as usize]};
+        // SAFETY: only strs have been written.
+        unsafe{ Str::from_utf8_unchecked(bytes) }
+    }
+    /// Get the length in bytes of what has been written so far.
+    pub fn len(&self) -> usize {
+        self.as_str().len()
+    }
+    /// Check if anything has been written.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Store the content in the struct itself if short enough, and in a box otherwise.
+    ///
+    /// If the content spilled to the heap and the capacity is more than needed,
+    ///  the allocation is shrunk, which the allocator might do by copying.
+    /// Use `with_capacity()` with the exact length to avoid that.
+    pub fn finish(self) -> Nbstr {
+        match try_stack(self.as_str().as_bytes()) {
+            Some(inline) => inline,// with_capacity() might have allocated too early
+            None => from_vec(self.heap),// counts the shrinking as a reallocation
+        }
+    }
+}
+
+impl fmt::Write for NbstrBuilder {
+    fn write_str(&mut self,  s: &str) -> fmt::Result {
+        self.push_str(s);
+        Ok(())
+    }
+    fn write_char(&mut self,  c: char) -> fmt::Result {
+        self.push(c);
+        Ok(())
+    }
+}
+
+impl fmt::Debug for NbstrBuilder {
+    fn fmt(&self,  fmtr: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmtr, "NbstrBuilder({:?})", self.as_str())
+    }
+}
@@ -0,0 +1,208 @@
+/* Copyright 2016 Torbjørn Birch Moltu
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use Nbstr;
+#[cfg(not(all(feature="64as48bit_hack", target_arch="x86_64")))]
+use nbstr;
+use Repr;
+use stats;
+use shared::{Protected, copy_bytes,from_boxed_bytes,from_vec,take_boxed_bytes,variant_name};
+use core::cmp::Ordering;
+use core::convert::TryFrom;
+use core::ops::Deref;
+use core::str as Str;
+use core::{ascii, error, fmt, hash};
+use core::borrow::Borrow;
+use alloc::borrow::ToOwned;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+
+
+/// A lean `Cow<'static, [u8]>` that cannot be written to.
+///
+/// Uses the same representations as Nbstr, but the content doesn't need to be UTF-8.
+#[derive(Clone, Default)]
+pub struct NbBytes(Nbstr);// might not be UTF-8, so never deref it as str
+
+
+  ////////////////////
+ // public methods //
+////////////////////
+
+impl NbBytes {
+    // keeping all public methods under one impl gives cleaner rustdoc
+    /// Store a `&'static [u8]` without copying it, like `From<&'static [u8]>`,
+    /// but can be used in `const`s and `static`s.
+    ///
+    /// # Panics
+    /// If the slice is 256 MiB or longer on 32-bit targets, which `From` stores out of line.
+    /// In a `const` this fails to compile, but at runtime use `From` for slices that might be that long.
+    #[cfg(not(all(feature="64as48bit_hack", target_arch="x86_64")))]
+    pub const fn from_static(b: &'static [u8]) -> Self {
+        NbBytes(nbstr::literal(b))
+    }
+    /// Store a `&'static [u8]` without copying it.
+    ///
+    /// Is not `const` with the 64as48bit_hack feature,
+    /// because pointers cannot be split into 48 bits at compile time.
+    #[cfg(all(feature="64as48bit_hack", target_arch="x86_64"))]
+    pub fn from_static(b: &'static [u8]) -> Self {
+        NbBytes(Nbstr::with_pointer(Nbstr::LITERAL, b))
+    }
+
+    /// Create a NbBytes from a borrowed slice with a limited lifetime.
+    /// If the slice is short enough it will be stored the inside struct itself and not boxed.
+    pub fn from_slice(b: &[u8]) -> Self {
+        NbBytes(copy_bytes(b))
+    }
+}
+
+
+  ///////////////
+ //Conversions//
+///////////////
+
+impl From<&'static [u8]> for NbBytes {
+    fn from(b: &'static [u8]) -> Self {
+        if !b.is_empty() {
+            stats::created(Repr::Static);
+        }
+        // Not from_static(), which cannot store giant slices when it's const.
+        NbBytes(Nbstr::with_pointer(Nbstr::LITERAL, b))
+    }
+}
+impl From<Box<[u8]>> for NbBytes {
+    fn from(b: Box<[u8]>) -> Self {
+        NbBytes(from_boxed_bytes(b))
+    }
+}
+impl From<Vec<u8>> for NbBytes {
+    fn from(v: Vec<u8>) -> Self {
+        NbBytes(from_vec(v))
+    }
+}
+/// Free, as a str is always valid bytes.
+impl From<Nbstr> for NbBytes {
+    fn from(z: Nbstr) -> Self {
+        NbBytes(z)
+    }
+}
+
+/// Checks that the bytes are UTF-8.
+impl TryFrom<NbBytes> for Nbstr {
+    type Error = FromUtf8Error;
+    fn try_from(b: NbBytes) -> Result<Nbstr, FromUtf8Error> {
+        match Str::from_utf8(&b) {
+            Ok(_) => Ok(b.0),
+            Err(e) => Err(FromUtf8Error{ bytes: b,  error: e }),
+        }
+    }
+}
+
+/// The error returned when converting a NbBytes that is not UTF-8 to a Nbstr.
+#[derive(Clone, PartialEq,Eq, Debug)]
+pub struct FromUtf8Error {
+    bytes: NbBytes,
+    error: Str::Utf8Error,
+}
+impl FromUtf8Error {
+    /// Get back the bytes that were attempted to convert.
+    pub fn into_bytes(self) -> NbBytes {
+        self.bytes
+    }
+    /// Get details about the conversion error.
+    pub fn utf8_error(&self) -> Str::Utf8Error {
+        self.error
+    }
+}
+impl fmt::Display for FromUtf8Error {
+    fn fmt(&self,  fmtr: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.error, fmtr)
+    }
+}
+impl error::Error for FromUtf8Error {}
+
+impl From<NbBytes> for Box<[u8]> {
+    fn from(mut b: NbBytes) -> Box<[u8]> {
+        take_boxed_bytes(&mut b.0).unwrap_or_else(|| b.deref().to_owned().into_boxed_slice() )
+    }
+}
+impl From<NbBytes> for Vec<u8> {
+    fn from(mut b: NbBytes) -> Vec<u8> {
+        take_boxed_bytes(&mut b.0)
+            .map(|b| b.into_vec() )
+            .unwrap_or_else(|| b.deref().to_owned() )
+    }
+}
+
+
+  ///////////
+ //Getters//
+///////////
+
+impl AsRef<[u8]> for NbBytes {
+    fn as_ref(&self) -> &[u8] {
+        self.0.get_slice()
+    }
+}
+impl Deref for NbBytes {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        self.0.get_slice()
+    }
+}
+impl Borrow<[u8]> for NbBytes {
+    fn borrow(&self) -> &[u8] {
+        self.0.get_slice()
+    }
+}
+
+
+  /////////////////
+ //Common traits//
+/////////////////
+
+impl hash::Hash for NbBytes {
+    fn hash<H:hash::Hasher>(&self,  h: &mut H) {
+        self.deref().hash(h);
+    }
+}
+impl PartialOrd for NbBytes {
+    fn partial_cmp(&self,  rhs: &Self) -> Option<Ordering> {
+        Some(self.cmp(rhs))
+    }
+}
+impl Ord for NbBytes {
+    fn cmp(&self,  rhs: &Self) -> Ordering {
+        self.deref().cmp(rhs.deref())
+    }
+}
+impl PartialEq for NbBytes {
+    fn eq(&self,  rhs: &Self) -> bool {
+        self.deref() == rhs.deref()
+    }
+} impl Eq for NbBytes {}
+
+/// Displays how the bytes are stored like Nbstr does, followed by them as an escaped byte string.
+impl fmt::Debug for NbBytes {
+    fn fmt(&self,  fmtr: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmtr, "{}: b\"", variant_name(&self.0))?;
+        for &b in self.deref() {
+            write!(fmtr, "{}", ascii::escape_default(b))?;
+        }
+        write!(fmtr, "\"")
+    }
+}
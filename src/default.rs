@@ -13,36 +13,9 @@
  * limitations under the License.
  */
 
-use shared::Protected;
-extern crate std;
-use std::mem;
-
-#[cfg(feature="unstable")]
-extern crate core;
-#[cfg(not(feature="unstable"))]
-mod core {
-    pub mod nonzero {
-        use std::ops::Deref;
-
-        /// A stable minimal stand-in for NonZero.
-        #[derive(Clone)]
-        pub struct NonZero<T> {
-            zeroable: T
-        }
-        impl<T> NonZero<T> {
-            pub unsafe fn new(not_zero: T) -> Self {
-                NonZero{zeroable: not_zero}
-            }
-        }
-        impl<T> Deref for NonZero<T> {
-            type Target = T;
-            fn deref(&self) -> &Self::Target {
-                &self.zeroable
-            }
-        }
-    }
-}
-use self::core::nonzero::NonZero;
+use shared::{NbCowIn,Protected};
+use core::mem;
+use core::num::NonZeroU8;
 
 
 
@@ -54,54 +27,109 @@ const POINTER_BYTES: usize = 4;
 const POINTER_BYTES: usize = 8;
 
 const DATA_SIZE: usize = 2*POINTER_BYTES;
-pub const MAX_LENGTH: usize = 0xffff_ffff_ffff_ffff_u64 as usize;
-
-#[allow(dead_code)]
-/// NonZero, never used
-pub const NONE: u8 = 0;
+const MAX_LENGTH: usize = 0xffff_ffff_ffff_ffff_u64 as usize;
 /// 1...MAX_STACK => stack string with length n
-pub const MAX_STACK: u8 = DATA_SIZE as u8;
-/// &'static str
-pub const LITERAL: u8 = MAX_STACK+1;
-/// Box<str>
-pub const BOX: u8 = MAX_STACK+2;
-// empty strings are stored as LITERAL with non-NULL but possibly invalid pointer and zero length
-//     (slices cannot have NULL pointers)
+const MAX_STACK: u8 = DATA_SIZE as u8;
+// The other variants follow MAX_STACK; see Protected.
+
+
 
+/// A tag byte followed by an unaligned (pointer, length) or the inline bytes.
+///
+/// Has no length limit, but is one byte bigger than `&str` (plus padding in arrays).
+pub struct Wide {
+    variant: NonZeroU8,
+    data: Data,
+}
+// SAFETY: pointers are only to data that is Send + Sync: str and Arc<str>
+unsafe impl Send for Wide {}
+unsafe impl Sync for Wide {}
+
+/// Keeps the pointer a pointer, so that it can be created in const fns.
+#[derive(Clone,Copy)]
+#[repr(C, packed)]// don't increase the size of Wide by aligning
+union Data {
+    inline: [u8; DATA_SIZE],
+    pointer: *const [u8],
+}
 
 
-/// A lean `Cow<'static, str>` that cannot be written to.
-#[cfg_attr(feature="unstable", unsafe_no_drop_flag)]//is set to empty literal during drop()
-pub struct Nbstr {
-    variant: NonZero<u8>,
-    data: [u8; DATA_SIZE],
+const fn from_parts(variant: u8,  data: Data) -> Wide {
+    // SAFETY: variants start at 1, as 0 is left for Option.
+    Wide{variant: unsafe{ NonZeroU8::new_unchecked(variant) },  data: data}
 }
 
+/// Used by `NbCow::from_static()`.
+pub const fn literal<'a>(s: &'static [u8]) -> NbCowIn<'a, Wide> {
+    NbCowIn::from_raw(from_parts(<Wide as Protected>::LITERAL, Data{ pointer: s }))
+}
+/// Used by `nbstr!()` to store short literals inline at compile time.
+pub const fn inline<'a>(s: &[u8]) -> NbCowIn<'a, Wide> {
+    let mut inline = [0; DATA_SIZE];
+    let mut i = 0;
+    while i < s.len() {
+        inline[i] = s[i];
+        i += 1;
+    }
+    NbCowIn::from_raw(from_parts(s.len() as u8, Data{ inline: inline }))
+}
 
-fn from_parts(variant: u8,  data: [u8; DATA_SIZE]) -> Nbstr {
-    Nbstr{variant: unsafe{ NonZero::new(variant) },  data: data}
+impl<'a> NbCowIn<'a, Wide> {
+    /// Store a `&'static str` without copying it, like `From<&'static str>`,
+    /// but can be used in `const`s and `static`s.
+    pub const fn from_static(s: &'static str) -> Self {
+        literal(s.as_bytes())
+    }
+    #[doc(hidden)]
+    /// Used by `nbstr!()`: store s inline if it's short enough, and as a literal otherwise.
+    pub const fn from_static_or_inline(s: &'static str) -> Self {
+        if s.is_empty()  ||  s.len() > MAX_STACK as usize {
+            literal(s.as_bytes())
+        } else {
+            inline(s.as_bytes())
+        }
+    }
 }
 
 
-impl Protected for Nbstr {
+impl Protected for Wide {
+    const MAX_STACK: u8 = MAX_STACK;
+    const MAX_LENGTH: usize = MAX_LENGTH;
+
     fn new(variant: u8) -> Self {
-        from_parts(variant, unsafe{ mem::uninitialized() })
+        from_parts(variant, Data{ inline: [0; DATA_SIZE] })
     }
-    fn with_pointer(variant: u8,  s: &str) -> Self {
-        from_parts(variant, unsafe{mem::transmute(s)} )
+    fn with_pointer(variant: u8,  s: *const [u8]) -> Self {
+        from_parts(variant, Data{ pointer: s })
     }
 
+    #[inline]
     fn variant(&self) -> u8 {
-        *self.variant
+        self.variant.get()
     }
     fn data(&mut self) -> &mut[u8] {
-        &mut self.data
+        // SAFETY: every bit pattern is a valid byte array, and u8 needs no alignment.
+        unsafe{ &mut self.data.inline }
     }
+    #[inline]
+    fn get_pointer(&self) -> *const [u8] {
+        // SAFETY: non-stack variants store pointer, and reading copies it out of the packed union.
+        unsafe{ self.data.pointer }
+    }
+    #[inline]
     fn get_slice(&self) -> &[u8] {
-        if *self.variant > MAX_STACK {
-            unsafe{ mem::transmute(self.data) }
+        if self.variant.get() > MAX_STACK {
+            // SAFETY: the pointer is to 'static or 'a memory, or to a box or Arc owned by self.
+            unsafe{ &*self.get_pointer() }
         } else {
-            &self.data[..*self.variant as usize]
+            // SAFETY: every bit pattern is a valid byte array, and u8 needs no alignment.
+            unsafe{ &self.data.inline[..self.variant.get() as usize] }
         }
     }
+    #[inline]
+    fn inline_words(&self) -> [usize; 2] {
+        // SAFETY: copies out of the packed union, so alignment doesn't matter,
+        //  and both types are plain bytes of the same size.
+        unsafe{ mem::transmute::<[u8; DATA_SIZE], [usize; 2]>(self.data.inline) }
+    }
 }
@@ -0,0 +1,167 @@
+/* Copyright 2016 Torbjørn Birch Moltu
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use Nbstr;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher,Hash,Hasher};
+use std::sync::OnceLock;
+use std::ops::Deref;
+use std::fmt;
+
+
+
+/// A Nbstr that hashes its content once when created, for use as a `HashMap` key.
+///
+/// `Hash` only writes the stored hash, which `PassThroughState` then uses as-is,
+/// so lookups in a `HashedNbstrMap` don't need to read the string.
+/// Comparing two HashedNbstrs for equality only compares the strings if the hashes are equal.
+///
+/// Because `Hash` differs from that of `str`, it doesn't implement `Borrow<str>`,
+/// and maps must be queried with another HashedNbstr.
+/// The hash is computed with random keys that are chosen once per process,
+/// so it is the same for equal strings within a process, but cannot be predicted
+/// by whoever chooses the strings, which would let them cause collisions.
+/// It differs between runs, so don't store it.
+#[derive(Clone)]
+pub struct HashedNbstr {
+    hash: u64,
+    string: Nbstr,
+}
+
+/// The keys used for every HashedNbstr in this process.
+static KEYS: OnceLock<RandomState> = OnceLock::new();
+
+/// A `HashMap` keyed by HashedNbstr that uses the stored hash directly.
+pub type HashedNbstrMap<V> = HashMap<HashedNbstr, V, PassThroughState>;
+
+impl HashedNbstr {
+    /// Hash the string and store the hash with it.
+    pub fn new(string: Nbstr) -> Self {
+        let hash = KEYS.get_or_init(RandomState::new).hash_one(&string);
+        HashedNbstr{ hash: hash,  string: string }
+    }
+    /// Get the hash that was computed when created.
+    pub fn hash_value(&self) -> u64 {
+        self.hash
+    }
+    /// Borrow the Nbstr.
+    pub fn as_nbstr(&self) -> &Nbstr {
+        &self.string
+    }
+    /// Get back the Nbstr.
+    pub fn into_nbstr(self) -> Nbstr {
+        self.string
+    }
+}
+
+/// The empty string with its real hash, so that it equals `HashedNbstr::from("")`.
+impl Default for HashedNbstr {
+    fn default() -> Self {
+        Self::new(Nbstr::default())
+    }
+}
+impl From<Nbstr> for HashedNbstr {
+    fn from(string: Nbstr) -> Self {
+        Self::new(string)
+    }
+}
+impl From<&'static str> for HashedNbstr {
+    fn from(s: &'static str) -> Self {
+        Self::new(Nbstr::from(s))
+    }
+}
+impl From<HashedNbstr> for Nbstr {
+    fn from(hashed: HashedNbstr) -> Nbstr {
+        hashed.string
+    }
+}
+
+impl Deref for HashedNbstr {
+    type Target = str;
+    fn deref(&self) -> &str {
+        &self.string
+    }
+}
+impl AsRef<str> for HashedNbstr {
+    fn as_ref(&self) -> &str {
+        &self.string
+    }
+}
+
+impl Hash for HashedNbstr {
+    fn hash<H:Hasher>(&self,  h: &mut H) {
+        h.write_u64(self.hash);
+    }
+}
+impl PartialEq for HashedNbstr {
+    fn eq(&self,  rhs: &Self) -> bool {
+        self.hash == rhs.hash  &&  self.string == rhs.string
+    }
+} impl Eq for HashedNbstr {}
+impl PartialOrd for HashedNbstr {
+    fn partial_cmp(&self,  rhs: &Self) -> Option<Ordering> {
+        Some(self.cmp(rhs))
+    }
+}
+/// Orders by the strings, not the hashes.
+impl Ord for HashedNbstr {
+    fn cmp(&self,  rhs: &Self) -> Ordering {
+        self.string.cmp(&rhs.string)
+    }
+}
+impl fmt::Display for HashedNbstr {
+    fn fmt(&self,  fmtr: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.string, fmtr)
+    }
+}
+/// Displays like Nbstr does.
+impl fmt::Debug for HashedNbstr {
+    fn fmt(&self,  fmtr: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.string, fmtr)
+    }
+}
+
+
+/// A `BuildHasher` for maps with HashedNbstr keys, that uses the stored hash as-is.
+#[derive(Clone,Copy, Default, Debug)]
+pub struct PassThroughState;
+impl BuildHasher for PassThroughState {
+    type Hasher = PassThroughHasher;
+    fn build_hasher(&self) -> PassThroughHasher {
+        PassThroughHasher(0xcbf2_9ce4_8422_2325)// FNV offset basis
+    }
+}
+
+/// The `Hasher` created by `PassThroughState`.
+///
+/// Returns the last `u64` written to it. Other types are mixed in with a simple and weak hash,
+///  so it shouldn't be used for other keys than HashedNbstr.
+#[derive(Clone,Copy, Debug)]
+pub struct PassThroughHasher(u64);
+impl Hasher for PassThroughHasher {
+    fn write_u64(&mut self,  hash: u64) {
+        self.0 = hash;
+    }
+    fn write(&mut self,  bytes: &[u8]) {
+        for &b in bytes {// FNV-1a
+            self.0 = (self.0 ^ b as u64).wrapping_mul(0x100_0000_01b3);
+        }
+    }
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
@@ -0,0 +1,130 @@
+/* Copyright 2016 Torbjørn Birch Moltu
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use shared::{NbCowIn,Protected};
+use core::{cmp,mem,ptr};
+use core::num::NonZeroU8;
+
+
+
+/// A tag byte followed by an unaligned `*const [u8]` or up to N inline bytes.
+///
+/// Is N+1 bytes, but never smaller than `Wide`, as the pointer must fit.
+/// N must be between 1 and 250, so that the other variants fit in the tag.
+/// Has no length limit.
+pub struct InlineN<const N: usize> {
+    variant: NonZeroU8,
+    data: Data<N>,
+}
+// SAFETY: pointers are only to data that is Send + Sync: str and Arc<str>
+unsafe impl<const N: usize> Send for InlineN<N> {}
+unsafe impl<const N: usize> Sync for InlineN<N> {}
+
+#[derive(Clone,Copy)]
+#[repr(C, packed)]// don't increase the size of InlineN by aligning
+union Data<const N: usize> {
+    inline: [u8; N],
+    pointer: *const [u8],
+}
+
+
+impl<const N: usize> InlineN<N> {
+    /// assumes stack
+    #[inline]
+    fn inline(&self) -> &[u8; N] {
+        let inline = ptr::addr_of!(self.data) as *const [u8; N];
+        // SAFETY: as in data()
+        unsafe{ &*inline }
+    }
+}
+
+const fn from_parts<const N: usize>(variant: u8,  data: Data<N>) -> InlineN<N> {
+    // SAFETY: variants start at 1, as 0 is left for Option.
+    InlineN{variant: unsafe{ NonZeroU8::new_unchecked(variant) },  data: data}
+}
+
+impl<'a, const N: usize> NbCowIn<'a, InlineN<N>> {
+    /// Store a `&'static str` without copying it, like `From<&'static str>`,
+    /// but can be used in `const`s and `static`s.
+    pub const fn from_static(s: &'static str) -> Self {
+        NbCowIn::from_raw(from_parts(<InlineN<N> as Protected>::LITERAL, Data{ pointer: s.as_bytes() }))
+    }
+    #[doc(hidden)]
+    /// Store s inline if it's short enough, and as a literal otherwise.
+    pub const fn from_static_or_inline(s: &'static str) -> Self {
+        let s = s.as_bytes();
+        if s.is_empty()  ||  s.len() > N {
+            return NbCowIn::from_raw(from_parts(<InlineN<N> as Protected>::LITERAL, Data{ pointer: s }));
+        }
+        let mut inline = [0; N];
+        let mut i = 0;
+        while i < s.len() {
+            inline[i] = s[i];
+            i += 1;
+        }
+        NbCowIn::from_raw(from_parts(s.len() as u8, Data{ inline: inline }))
+    }
+}
+
+
+impl<const N: usize> Protected for InlineN<N> {
+    const MAX_STACK: u8 = {
+        assert!(N >= 1  &&  N <= 250, "InlineN<N> can only store between 1 and 250 bytes inline");
+        N as u8
+    };
+    const MAX_LENGTH: usize = usize::MAX;
+
+    fn new(variant: u8) -> Self {
+        from_parts(variant, Data{ inline: [0; N] })// inline strings must be zero-padded
+    }
+    fn with_pointer(variant: u8,  s: *const [u8]) -> Self {
+        from_parts(variant, Data{ pointer: s })
+    }
+
+    #[inline]
+    fn variant(&self) -> u8 {
+        self.variant.get()
+    }
+    fn data(&mut self) -> &mut[u8] {
+        // The compiler doesn't know that [u8; N] needs no alignment, so cast instead of taking &mut of the field.
+        let inline = ptr::addr_of_mut!(self.data) as *mut [u8; N];
+        // SAFETY: every field of a repr(C) union starts at offset 0, and u8 needs no alignment.
+        //  The inline bytes are always initialized, as new() zeroes them.
+        unsafe{ &mut *inline }
+    }
+    #[inline]
+    fn get_pointer(&self) -> *const [u8] {
+        // SAFETY: non-stack variants store pointer, and reading copies it out of the packed union.
+        unsafe{ self.data.pointer }
+    }
+    #[inline]
+    fn get_slice(&self) -> &[u8] {
+        if self.variant.get() > Self::MAX_STACK {
+            // SAFETY: the pointer is to 'static or 'a memory, or to a box or Arc owned by self.
+            unsafe{ &*self.get_pointer() }
+        } else {
+            &self.inline()[..self.variant.get() as usize]
+        }
+    }
+    #[inline]
+    fn inline_words(&self) -> [usize; 2] {
+        // Only the first N bytes are initialized if N is smaller than a pointer.
+        let mut padded = [0u8; 2*mem::size_of::<usize>()];
+        let len = cmp::min(N, padded.len());
+        padded[..len].copy_from_slice(&self.inline()[..len]);
+        // SAFETY: both are plain bytes of the same size.
+        unsafe{ mem::transmute::<[u8; 2*mem::size_of::<usize>()], [usize; 2]>(padded) }
+    }
+}
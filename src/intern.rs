@@ -0,0 +1,137 @@
+/* Copyright 2016 Torbjørn Birch Moltu
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! A global, thread-safe string interner.
+//!
+//! `Nbstr::intern()` returns Nbstrs that share their `Arc<str>` with the interner,
+//! so two interned Nbstrs are equal exactly when they point to the same string.
+//!
+//! Interned strings are not removed when the last Nbstr referencing them is dropped;
+//! call `collect()` to do that.
+
+use Repr;
+use stats;
+use shared::{NbCowIn,Protected};
+use layouts::Layout;
+use std::collections::HashSet;
+use std::sync::{Arc,Mutex,OnceLock,PoisonError};
+
+struct Interner {
+    strings: HashSet<Arc<str>>,
+    /// Keeps a reference to seeded strings so that they are never collected.
+    seeded: HashSet<Arc<str>>,
+    bytes: usize,
+    hits: u64,
+    misses: u64,
+}
+
+static INTERNER: OnceLock<Mutex<Interner>> = OnceLock::new();
+
+fn with_interner<R, F:FnOnce(&mut Interner)->R>(f: F) -> R {
+    let mutex = INTERNER.get_or_init(|| Mutex::new(Interner {
+        strings: HashSet::new(),
+        seeded: HashSet::new(),
+        bytes: 0,
+        hits: 0,
+        misses: 0,
+    }));
+    // The interner is never left in an inconsistent state, so poisoning can be ignored.
+    let mut interner = mutex.lock().unwrap_or_else(PoisonError::into_inner);
+    f(&mut interner)
+}
+
+impl Interner {
+    fn get_or_insert(&mut self,  s: &str) -> Arc<str> {
+        if let Some(existing) = self.strings.get(s) {
+            self.hits += 1;
+            return existing.clone();
+        }
+        self.misses += 1;
+        self.bytes += s.len();
+        let new: Arc<str> = Arc::from(s);
+        self.strings.insert(new.clone());
+        return new;
+    }
+}
+
+
+/// Used by `Nbstr::intern()`.
+pub fn intern<L: Layout>(s: &str) -> NbCowIn<'static, L> {
+    if s.is_empty() {
+        return NbCowIn::default();
+    }
+    let arc = with_interner(|interner| interner.get_or_insert(s) );
+    let arc = Arc::into_raw(arc);
+    stats::created(Repr::Interned);
+    NbCowIn::with_pointer(L::INTERNED, arc as *const [u8])
+}
+
+/// Intern these strings and keep them until the process exits,
+/// even if they aren't used and `collect()` is called.
+pub fn seed<I:IntoIterator<Item=&'static str>>(strs: I) {
+    with_interner(|interner| {
+        for s in strs {
+            let arc = interner.get_or_insert(s);
+            interner.seeded.insert(arc);
+        }
+    })
+}
+
+/// Remove interned strings that are not referenced by any Nbstr.
+///
+/// Returns the number of strings removed.
+pub fn collect() -> usize {
+    with_interner(|interner| {
+        let before = interner.strings.len();
+        let mut freed = 0;
+        // Only the interner can create new references to a string with a count of one,
+        // and it is locked.
+        interner.strings.retain(|s| {
+            let unused = Arc::strong_count(s) == 1;
+            if unused {
+                freed += s.len();
+            }
+            !unused
+        });
+        interner.bytes -= freed;
+        before - interner.strings.len()
+    })
+}
+
+/// Statistics about the interner.
+#[derive(Clone,Copy, Default, PartialEq,Eq, Debug)]
+pub struct Stats {
+    /// The number of distinct strings currently interned.
+    pub strings: usize,
+    /// The combined length of those strings.
+    pub bytes: usize,
+    /// How many of those were seeded.
+    pub seeded: usize,
+    /// The number of times an already interned string was found.
+    pub hits: u64,
+    /// The number of times a string had to be added.
+    pub misses: u64,
+}
+
+/// Get current statistics about the interner.
+pub fn stats() -> Stats {
+    with_interner(|interner| Stats {
+        strings: interner.strings.len(),
+        bytes: interner.bytes,
+        seeded: interner.seeded.len(),
+        hits: interner.hits,
+        misses: interner.misses,
+    })
+}
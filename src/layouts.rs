@@ -0,0 +1,92 @@
+/* Copyright 2016 Torbjørn Birch Moltu
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! The ways NbCow can be laid out in memory.
+//!
+//! Every layout can be used at the same time as `NbCowIn<'a, L>` or `NbstrIn<L>`,
+//! so a library can pin the layout it needs without affecting other crates.
+//! `Nbstr` and `NbCow<'a>` use `Selected`, which the cargo features and target pointer width choose.
+//!
+//! Conversions between layouts with `From` or `NbCow::into_layout()` keep how the string is stored,
+//! except that inline strings are boxed if they are too long to be inline in the new layout.
+
+use shared::{NbCowIn,Protected};
+
+pub use default::Wide;
+pub use no_giants::NoGiants;
+pub use inline_n::InlineN;
+#[cfg(target_pointer_width="64")]
+pub use len32::Len32;
+#[cfg(target_arch="x86_64")]
+pub use x64as48bit_hack::Packed48;
+
+/// Implemented by the layouts in this module, and cannot be implemented outside this crate.
+pub trait Layout: Protected {}
+impl Layout for Wide {}
+impl Layout for NoGiants {}
+impl<const N: usize> Layout for InlineN<N> {}
+#[cfg(target_pointer_width="64")]
+impl Layout for Len32 {}
+#[cfg(target_arch="x86_64")]
+impl Layout for Packed48 {}
+
+// NoGiants is as small as &str, and only needs an extra allocation for strings of 256 MiB
+//  or more on 32-bit targets; on 64-bit targets the limit is more than any address space.
+/// The layout used by `Nbstr` and `NbCow`, as selected by cargo features and the target.
+#[cfg(not(all(feature="64as48bit_hack", target_arch="x86_64")))]
+pub type Selected = NoGiants;
+/// The layout used by `Nbstr` and `NbCow`, as selected by cargo features and the target.
+#[cfg(all(feature="64as48bit_hack", target_arch="x86_64"))]
+pub type Selected = Packed48;
+
+
+// From<NbCowIn<L>> for NbCowIn<M> would conflict with From<T> for T.
+macro_rules! convert {($($from:ident => $to:ident),+) => {$(
+    /// Keeps how the string is stored; see `NbCow::into_layout()`.
+    impl<'a> From<NbCowIn<'a, $from>> for NbCowIn<'a, $to> {
+        fn from(z: NbCowIn<'a, $from>) -> Self {
+            z.into_layout()
+        }
+    }
+)+}}
+convert!{Wide => NoGiants,  NoGiants => Wide}
+#[cfg(target_pointer_width="64")]
+convert!{Wide => Len32,  Len32 => Wide,  NoGiants => Len32,  Len32 => NoGiants}
+#[cfg(target_arch="x86_64")]
+convert!{Len32 => Packed48,  Packed48 => Len32}
+
+// Between two InlineN would also conflict with From<T> for T; use into_layout() for that.
+macro_rules! convert_inline_n {($($layout:ident),+) => {$(
+    /// Keeps how the string is stored; see `NbCow::into_layout()`.
+    impl<'a, const N: usize> From<NbCowIn<'a, $layout>> for NbCowIn<'a, InlineN<N>> {
+        fn from(z: NbCowIn<'a, $layout>) -> Self {
+            z.into_layout()
+        }
+    }
+    /// Keeps how the string is stored; see `NbCow::into_layout()`.
+    impl<'a, const N: usize> From<NbCowIn<'a, InlineN<N>>> for NbCowIn<'a, $layout> {
+        fn from(z: NbCowIn<'a, InlineN<N>>) -> Self {
+            z.into_layout()
+        }
+    }
+)+}}
+convert_inline_n!{Wide, NoGiants}
+#[cfg(target_pointer_width="64")]
+convert_inline_n!{Len32}
+#[cfg(target_arch="x86_64")]
+convert_inline_n!{Packed48}
+#[cfg(target_arch="x86_64")]
+convert!{Wide => Packed48,  Packed48 => Wide,  NoGiants => Packed48,  Packed48 => NoGiants}
+
@@ -0,0 +1,186 @@
+/* Copyright 2016 Torbjørn Birch Moltu
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use shared::{NbCowIn,Protected,OutOfLine};
+use core::{mem,ptr};
+use core::num::NonZeroU8;
+
+
+
+const DATA_SIZE: usize = 8+4;
+const MAX_32BIT: usize = 0xffff_ffff;
+/// 1...12 => stack string with length n
+const MAX_STACK: u8 = DATA_SIZE as u8;
+// The other variants follow MAX_STACK; see Protected.
+/// Points to an OutOfLine, which has the real length and variant.
+const OUT_OF_LINE: u8 = MAX_STACK+6;
+
+
+
+/// A tag byte followed by an unaligned full pointer and a 32-bit length, or the inline bytes.
+///
+/// Is 13 bytes like `Packed48`, but makes no assumptions about the address space,
+/// and `from_static()` can be `const`.
+/// Strings of 4 GiB or more are stored through an extra allocation.
+/// Only exists on 64-bit targets, as on others it would be the same as `Wide`.
+pub struct Len32 {
+    variant: NonZeroU8,
+    data: Data,
+}
+// SAFETY: pointers are only to data that is Send + Sync: str, Arc<str> and OutOfLine
+unsafe impl Send for Len32 {}
+unsafe impl Sync for Len32 {}
+
+#[derive(Clone,Copy)]
+#[repr(C, packed)]// don't increase the size of Len32 by aligning
+union Data {
+    inline: [u8; DATA_SIZE],
+    pointer: Pointer,
+}
+#[derive(Clone,Copy)]
+#[repr(C, packed)]
+struct Pointer {
+    pointer: *const u8,
+    length: u32,
+}
+
+impl Len32 {
+    /// the OutOfLine of a str that is 4 GiB or longer
+    #[inline]
+    fn out_of_line(&self) -> Option<&OutOfLine> {
+        if self.variant.get() == OUT_OF_LINE {
+            // SAFETY: the pointer came from OutOfLine::allocate() in with_pointer(), and is freed only by drop.
+            Some(unsafe{ &*(self.pointer().pointer as *const OutOfLine) })
+        } else {
+            None
+        }
+    }
+    #[inline]
+    fn pointer(&self) -> Pointer {
+        // SAFETY: non-stack variants store pointer, and reading copies it out of the packed union.
+        unsafe{ self.data.pointer }
+    }
+}
+impl Drop for Len32 {
+    fn drop(&mut self) {
+        if self.out_of_line().is_some() {
+            // SAFETY: the pointer itself rather than the reference from out_of_line(), so it may be freed.
+            unsafe{ OutOfLine::free(self.pointer().pointer as *mut OutOfLine) };
+        }
+    }
+}
+
+
+const fn from_parts(variant: u8,  data: Data) -> Len32 {
+    // SAFETY: variants start at 1, as 0 is left for Option.
+    Len32{variant: unsafe{ NonZeroU8::new_unchecked(variant) },  data: data}
+}
+
+/// Used by `NbCow::from_static()`.
+pub const fn literal<'a>(s: &'static [u8]) -> NbCowIn<'a, Len32> {
+    // Cannot allocate an OutOfLine at compile time, but From<&'static str> can.
+    assert!(s.len() <= MAX_32BIT, "The str is too long for a const Nbstr");
+    let pointer = Pointer{ pointer: s.as_ptr(),  length: s.len() as u32 };
+    NbCowIn::from_raw(from_parts(<Len32 as Protected>::LITERAL, Data{ pointer: pointer }))
+}
+/// Used by `nbstr!()` to store short literals inline at compile time.
+pub const fn inline<'a>(s: &[u8]) -> NbCowIn<'a, Len32> {
+    let mut inline = [0; DATA_SIZE];
+    let mut i = 0;
+    while i < s.len() {
+        inline[i] = s[i];
+        i += 1;
+    }
+    NbCowIn::from_raw(from_parts(s.len() as u8, Data{ inline: inline }))
+}
+
+impl<'a> NbCowIn<'a, Len32> {
+    /// Store a `&'static str` without copying it, like `From<&'static str>`,
+    /// but can be used in `const`s and `static`s.
+    ///
+    /// # Panics
+    /// If the str is 4 GiB or longer, which `From` stores out of line.
+    /// In a `const` this fails to compile, but at runtime use `From` for strs that might be that long.
+    pub const fn from_static(s: &'static str) -> Self {
+        literal(s.as_bytes())
+    }
+    #[doc(hidden)]
+    /// Used by `nbstr!()`: store s inline if it's short enough, and as a literal otherwise.
+    pub const fn from_static_or_inline(s: &'static str) -> Self {
+        if s.is_empty()  ||  s.len() > MAX_STACK as usize {
+            literal(s.as_bytes())
+        } else {
+            inline(s.as_bytes())
+        }
+    }
+}
+
+
+impl Protected for Len32 {
+    const MAX_STACK: u8 = MAX_STACK;
+    const MAX_LENGTH: usize = usize::MAX;
+
+    fn new(variant: u8) -> Self {
+        from_parts(variant, Data{ inline: [0; DATA_SIZE] })// inline strings must be zero-padded
+    }
+    fn with_pointer(variant: u8,  s: *const [u8]) -> Self {
+        if s.len() > MAX_32BIT {
+            let pointer = OutOfLine::allocate(variant, s) as *const u8;
+            return from_parts(OUT_OF_LINE, Data{ pointer: Pointer{ pointer: pointer,  length: 0 } });
+        }
+        let pointer = Pointer{ pointer: s as *const u8,  length: s.len() as u32 };
+        from_parts(variant, Data{ pointer: pointer })
+    }
+
+    #[inline]
+    fn variant(&self) -> u8 {
+        match self.out_of_line() {
+            Some(out_of_line) => out_of_line.variant(),
+            None => self.variant.get(),
+        }
+    }
+    fn data(&mut self) -> &mut[u8] {
+        // SAFETY: every bit pattern is a valid byte array, and u8 needs no alignment.
+        unsafe{ &mut self.data.inline }
+    }
+    #[inline]
+    fn get_pointer(&self) -> *const [u8] {
+        match self.out_of_line() {
+            Some(out_of_line) => out_of_line.get_pointer(),
+            None => {
+                let pointer = self.pointer();
+                ptr::slice_from_raw_parts(pointer.pointer,  pointer.length as usize)
+            }
+        }
+    }
+    #[inline]
+    fn get_slice(&self) -> &[u8] {
+        if self.variant.get() > MAX_STACK {
+            // SAFETY: the pointer is to 'static or 'a memory, or to a box or Arc owned by self.
+            unsafe{ &*self.get_pointer() }
+        } else {
+            // SAFETY: every bit pattern is a valid byte array, and u8 needs no alignment.
+            unsafe{ &self.data.inline[..self.variant.get() as usize] }
+        }
+    }
+    #[inline]
+    fn inline_words(&self) -> [usize; 2] {
+        let mut padded = [0u8; 16];
+        // SAFETY: as in get_slice()
+        padded[..DATA_SIZE].copy_from_slice(unsafe{ &self.data.inline });
+        // SAFETY: both are plain bytes of the same size.
+        unsafe{ mem::transmute::<[u8; 16], [usize; 2]>(padded) }
+    }
+}
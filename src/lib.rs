@@ -16,13 +16,18 @@
 
 //! A lean `Cow<'static, str>` that cannot be written to.
 //!
-//! Size is reduced by replacing `String` with `Box<str>``, which removes one `usize`, at the cost of some reallocation.
+//! Size is reduced by replacing `String` with `Box<str>`, which removes one `usize`, at the cost of some reallocation.
+//! Strings that are cloned a lot can instead be stored in an `Arc<str>`, which makes cloning them cheap.
 //!
 //! To avoid boxing when possible, short `str`s can be stored inside the struct itself, replacing pointer and length.
 //! The length of the short str is then stored as a part of the tag/discriminant, which is why Nbstr is a struct and not an enum.
 //! The definition of 'short' depends on architecture and features.
 //!
-//! There are four variants of nbstr; See README for details.
+//! There are five layouts of nbstr; See README and the `layouts` module for details.
+//! `Nbstr` uses the one selected by cargo features, and `NbCowIn<'a, L>` can use any of them.
+//! `NbstrN<N>` stores up to N bytes inline, for when the default is too short.
+//!
+//! `NbCow<'a>` can also borrow strs with a limited lifetime, and is to `Nbstr` what `Cow<'a, str>` is to `Cow<'static, str>`.
 //!
 //! # Examples
 //!
@@ -51,31 +56,137 @@
 //! ```
 
 // Overview:
-// shared.rs: the public interface and code used in all variants.
-// other: variant-specific code and implementation details.
-
+// shared.rs: the public interface and code used in all layouts.
+// layouts.rs: the Layout trait and which layout is selected.
+// bytes.rs: NbBytes, which reuses the variants of Nbstr for [u8].
+// builder.rs: NbstrBuilder, which writes into an inline buffer before spilling to the heap.
+// split.rs: iterators over parts of a Nbstr.
+// stats.rs: global counters for how Nbstrs are stored, with the stats feature.
+// os.rs: NbOsStr and NbPath, which store the encoded bytes of OsStr.
+// hashed.rs: HashedNbstr, which stores its hash for use as a HashMap key.
+// intern.rs: the global interner used by Nbstr::intern().
+// serde_impls.rs: Serialize and Deserialize, with the serde feature.
+// default.rs, no_giants.rs, len32.rs, x64as48bit_hack.rs: the layouts Wide, NoGiants, Len32 and Packed48.
+// inline_n.rs: the layout InlineN<N>, which stores N bytes inline.
 
-// unstable features
-#![cfg_attr(feature="unstable", feature(associated_consts,  nonzero, unsafe_no_drop_flag))]
 
+#![no_std]
 #![warn(missing_docs)]
 #![cfg_attr(feature="clippy", feature(plugin))]
 #![cfg_attr(feature="clippy", plugin(clippy))]
+// Style lints for things I think are OK; see README.
+#![allow(clippy::needless_return, clippy::redundant_field_names, clippy::should_implement_trait,
+         clippy::redundant_static_lifetimes)]
+// Hash is that of str, so Borrow<[u8]> only works for comparisons.
+#![allow(clippy::impl_hash_borrow_with_str_and_bytes)]
+// Drop is not implemented when testing.
+#![cfg_attr(test, allow(clippy::forget_non_drop))]
+
+extern crate alloc;
+#[cfg(any(feature="std", test))]
+extern crate std;
+#[cfg(feature="serde")]
+extern crate serde;
 
 mod shared;
+mod bytes;
+mod builder;
+mod split;
+#[cfg(feature="stats")]
+pub mod stats;
+#[cfg(not(feature="stats"))]
+mod stats;
+#[cfg(feature="std")]
+mod os;
+#[cfg(feature="std")]
+mod hashed;
+#[cfg(feature="std")]
+pub mod intern;
+#[cfg(feature="serde")]
+mod serde_impls;
+#[cfg(feature="serde")]
+pub use serde_impls::deserialize_literal;
 
-#[cfg(not(any(feature="no_giants", all(feature="64as48bit_hack", target_arch="x86_64"))))]
+pub mod layouts;
 mod default;
-#[cfg(all(feature="no_giants", not(all(feature="64as48bit_hack", target_arch="x86_64"))))]
 mod no_giants;
-#[cfg(all(feature="64as48bit_hack", target_arch="x86_64"))]
+#[cfg(target_pointer_width="64")]
+mod len32;
+mod inline_n;
+#[cfg(target_arch="x86_64")]
 mod x64as48bit_hack;// cannot start with a number
-mod nbstr {// rename variants
-    #[cfg(not(any(feature="no_giants", all(feature="64as48bit_hack", target_arch="x86_64"))))]
-    pub use default::*;
-    #[cfg(all(feature="no_giants", not(all(feature="64as48bit_hackt", target_arch="x86_64"))))]
+#[cfg(not(all(feature="64as48bit_hack", target_arch="x86_64")))]
+mod nbstr {// the const fns of the selected layout; see layouts::Selected
     pub use no_giants::*;
-    #[cfg(all(feature="64as48bit_hack", target_arch="x86_64"))]
-    pub use x64as48bit_hack::*;
 }
-pub use nbstr::Nbstr;
+/// Create a `Nbstr` from one or more string literals at compile time.
+///
+/// The literals are concatenated, and the result is stored inline if it's short enough,
+/// and as a `&'static str` otherwise. Can be used in `const`s and `static`s.
+///
+/// # Examples
+///
+/// ```rust
+/// #[macro_use] extern crate nbstr;
+/// use nbstr::Nbstr;
+///
+/// static KEYWORDS: [Nbstr; 3] = [nbstr!("if"), nbstr!("else"), nbstr!("el", "if")];
+/// const LONG: Nbstr = nbstr!("A string that is too long to be stored inline");
+///
+/// fn main() {
+///     assert_eq!(&*KEYWORDS[2], "elif");
+///     assert_eq!(format!("{:?}", KEYWORDS[0]), "stack: if");
+///     assert_eq!(format!("{:?}", LONG), "literal: A string that is too long to be stored inline");
+/// }
+/// ```
+#[cfg(not(all(feature="64as48bit_hack", target_arch="x86_64")))]
+#[macro_export]
+macro_rules! nbstr {
+    ($($s:expr),+ $(,)*) => {{
+        const NBSTR: $crate::Nbstr = $crate::Nbstr::from_static_or_inline(concat!($($s),+));
+        NBSTR
+    }};
+}
+/// Create a `Nbstr` from one or more string literals.
+///
+/// The literals are concatenated, and the result is stored inline if it's short enough,
+/// and as a `&'static str` otherwise.
+/// Cannot be used in `const`s and `static`s when the Packed48 layout is selected,
+/// because pointers cannot be split into 48 bits at compile time.
+///
+/// # Examples
+///
+/// ```rust
+/// #[macro_use] extern crate nbstr;
+///
+/// fn main() {
+///     assert_eq!(&*nbstr!("el", "if"), "elif");
+///     assert_eq!(format!("{:?}", nbstr!("if")), "stack: if");
+/// }
+/// ```
+#[cfg(all(feature="64as48bit_hack", target_arch="x86_64"))]
+#[macro_export]
+macro_rules! nbstr {
+    ($($s:expr),+ $(,)*) => {
+        $crate::Nbstr::from_static_or_inline(concat!($($s),+))
+    };
+}
+
+pub use shared::{NbCowIn,Repr,NbstrError};
+pub use bytes::{NbBytes,FromUtf8Error};
+pub use builder::NbstrBuilder;
+pub use split::{Split,SplitN,Lines,SplitWhitespace};
+#[cfg(feature="std")]
+pub use os::{NbOsStr,NbPath};
+#[cfg(feature="std")]
+pub use hashed::{HashedNbstr,HashedNbstrMap,PassThroughState,PassThroughHasher};
+/// A lean `Cow<'a, str>` that cannot be written to, with the layout selected by cargo features.
+pub type NbCow<'a> = NbCowIn<'a, layouts::Selected>;
+/// A lean `Cow<'static, str>` that cannot be written to, with the layout selected by cargo features.
+pub type Nbstr = NbCow<'static>;
+/// A `Nbstr` with the layout `L`, which is the same regardless of which features are enabled.
+pub type NbstrIn<L> = NbCowIn<'static, L>;
+/// A `Nbstr` that stores up to N bytes inside the struct itself.
+///
+/// N can be between 1 and 250; see `layouts::InlineN`.
+pub type NbstrN<const N: usize> = NbstrIn<layouts::InlineN<N>>;
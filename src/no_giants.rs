@@ -13,11 +13,9 @@
  * limitations under the License.
  */
 
-use shared::Protected;
-extern crate std;
-use std::{mem,slice};
-extern crate core;
-use self::core::nonzero::NonZero;
+use shared::{NbCowIn,Protected,OutOfLine};
+use core::{mem,ptr};
+use core::num::NonZeroUsize;
 
 
 
@@ -30,87 +28,178 @@ const POINTER_BYTES: usize = 4;
 const POINTER_BYTES: usize = 8;
 
 const SIZE: usize = 2*POINTER_BYTES;
-// need to store DATA_SIZE+3 combinations
+// need to store DATA_SIZE+6 combinations
 // even if you can subtract one, you need one extra bit.
-// 16bit=4+2=3bit=shift 13, 32bit=8+2=4bit=shift 28, 64bit=16+2=5bit=shift 59
+// 16bit=4+5=4bit=shift 12, 32bit=8+5=4bit=shift 28, 64bit=16+5=5bit=shift 59
 // start with 16 bits: 16.trailing_zeros()==4, which is one too much.
 // when only one bit is set, n.leading_zeros()==n::BITS-1-n.trailing_zeros()
 // const SHIFT_BITS: usize = (8*POINTER_BYTES).leading_zeros() as usize;
+// GIANT is one more, which still fits.
 #[cfg(target_pointer_width="16")]
-const SHIFT_BITS: usize = 13;
+const SHIFT_BITS: usize = 12;
 #[cfg(target_pointer_width="32")]
 const SHIFT_BITS: usize = 28;
 #[cfg(target_pointer_width="64")]
 const SHIFT_BITS: usize = 59;
 
-pub const MAX_LENGTH: usize = (1 << SHIFT_BITS) -1;
-
-#[allow(dead_code)]
-/// NonZero, never used
-pub const NONE: u8 = 0;
+/// Longer strs are stored as GIANT.
+pub(crate) const MAX_DIRECT_LENGTH: usize = (1 << SHIFT_BITS) -1;
 /// 1...MAX_STACK => stack string with length n
-pub const MAX_STACK: u8 = (SIZE-1) as u8;//one byte is used for variant
-/// &'static str
-pub const LITERAL: u8 = MAX_STACK+1;
-/// Box<str>
-pub const BOX: u8 = MAX_STACK+2;
-// empty strings are stored as LITERAL with non-NULL but possibly invalid pointer and zero length
-//     (slices cannot have NULL pointers)
+const MAX_STACK: u8 = (SIZE-1) as u8;//one byte is used for variant
+// The other variants follow MAX_STACK; see Protected.
+/// Points to an OutOfLine, which has the real length and variant.
+const GIANT: u8 = MAX_STACK+6;
 
 
 
-/// A lean `Cow<'static, str>` that cannot be written to.
-#[unsafe_no_drop_flag]// is set to empty literal during drop()
+/// Stores the variant in the most significant bits of length.
+///
+/// Is as big as `&str`. Strings that are too long to leave room for the variant
+/// are stored through an extra allocation, which only happens on 32- and 16-bit targets
+/// as the limit is bigger than any 64-bit address space.
+/// Is the default unless `64as48bit_hack` is enabled.
 #[repr(C)]// endian-dependent order
-pub struct Nbstr {
+pub struct NoGiants {
     // The byte that contains variant cannot be in the middle of an array.
     #[cfg(target_endian="big")]
-    length: NonZero<usize>,
+    length: NonZeroUsize,
     pointer: *const u8,
     #[cfg(target_endian="little")]
-    length: NonZero<usize>,
+    length: NonZeroUsize,
 }
+// SAFETY: pointers are only to data that is Send + Sync: str, Arc<str> and OutOfLine
+unsafe impl Send for NoGiants {}
+unsafe impl Sync for NoGiants {}
 
+impl NoGiants {
+    /// the OutOfLine of a str that is longer than MAX_DIRECT_LENGTH
+    #[inline]
+    fn giant(&self) -> Option<&OutOfLine> {
+        if (self.length.get() >> SHIFT_BITS) as u8 == GIANT {
+            // SAFETY: pointer came from OutOfLine::allocate() in with_pointer(), and is freed only by drop.
+            Some(unsafe{ &*(self.pointer as *const OutOfLine) })
+        } else {
+            None
+        }
+    }
+}
+impl Drop for NoGiants {
+    fn drop(&mut self) {
+        if self.giant().is_some() {
+            // SAFETY: the pointer itself rather than the reference from giant(), so it may be freed.
+            unsafe{ OutOfLine::free(self.pointer as *mut OutOfLine) };
+        }
+    }
+}
+
+
+/// Used by `NbCow::from_static()`.
+pub const fn literal<'a>(s: &'static [u8]) -> NbCowIn<'a, NoGiants> {
+    // Cannot allocate an OutOfLine at compile time, but From<&'static str> can.
+    assert!(s.len() <= MAX_DIRECT_LENGTH, "The str is too long for a const Nbstr");
+    let variant = <NoGiants as Protected>::LITERAL;
+    NbCowIn::from_raw(NoGiants {
+        pointer: s.as_ptr(),
+        // SAFETY: variant is nonzero, and the length doesn't overlap it.
+        length: unsafe{ NonZeroUsize::new_unchecked( ((variant as usize) << SHIFT_BITS)  |  s.len() )},
+    })
+}
+/// Used by `nbstr!()` to store short literals inline at compile time.
+pub const fn inline<'a>(s: &[u8]) -> NbCowIn<'a, NoGiants> {
+    let mut arr = [0; SIZE];
+    // the most significant byte of length contains the variant
+    let (variant_byte, start) = if cfg!(target_endian="little") {(SIZE-1, 0)} else {(0, 1)};
+    arr[variant_byte] = (s.len() << (SHIFT_BITS - 8*(POINTER_BYTES-1))) as u8;
+    let mut i = 0;
+    while i < s.len() {
+        arr[start+i] = s[i];
+        i += 1;
+    }
+    // SAFETY: the variant byte is nonzero, and a pointer that's never dereferenced can have any bits.
+    NbCowIn::from_raw(unsafe{ mem::transmute::<[u8; SIZE], NoGiants>(arr) })
+}
+
+impl<'a> NbCowIn<'a, NoGiants> {
+    /// Store a `&'static str` without copying it, like `From<&'static str>`,
+    /// but can be used in `const`s and `static`s.
+    ///
+    /// # Panics
+    /// If the str is 256 MiB or longer on 32-bit targets, which `From` stores out of line.
+    /// In a `const` this fails to compile, but at runtime use `From` for strs that might be that long.
+    pub const fn from_static(s: &'static str) -> Self {
+        literal(s.as_bytes())
+    }
+    #[doc(hidden)]
+    /// Used by `nbstr!()`: store s inline if it's short enough, and as a literal otherwise.
+    pub const fn from_static_or_inline(s: &'static str) -> Self {
+        if s.is_empty()  ||  s.len() > MAX_STACK as usize {
+            literal(s.as_bytes())
+        } else {
+            inline(s.as_bytes())
+        }
+    }
+}
+
+
+impl Protected for NoGiants {
+    const MAX_STACK: u8 = MAX_STACK;
+    const MAX_LENGTH: usize = usize::MAX;
 
-impl Protected for Nbstr {
     fn new(variant: u8) -> Self {
-        Nbstr {
-            length:  unsafe{ NonZero::new( (variant as usize) << SHIFT_BITS )},
-            pointer:  unsafe{ mem::uninitialized() },
+        NoGiants {
+            // SAFETY: variants start at 1, as 0 is left for Option.
+            length:  unsafe{ NonZeroUsize::new_unchecked( (variant as usize) << SHIFT_BITS )},
+            pointer: ptr::null(),// inline strings must be zero-padded
         }
     }
-    fn with_pointer(variant: u8,  s: &str) -> Self {
-        if cfg!(debug_assertions)  &&  s.len() > MAX_LENGTH {
-            if cfg!(test) {// dereferencing the test string would segfault
-                panic!(".len()={:x}, MAX_LENGTH={:x}", s.len(), MAX_LENGTH);
-            } else {
-                panic!("The string \"{}\"...\"{}\", with length {}, is too long for Nbstr.\n\
-                        Disable the \"tag_len\" feature.", &s[..20], &s[s.len()-20..], s.len());
-            }
+    fn with_pointer(variant: u8,  s: *const [u8]) -> Self {
+        if s.len() > MAX_DIRECT_LENGTH {// would change the variant
+            return NoGiants {
+                pointer: OutOfLine::allocate(variant, s) as *const u8,
+                // SAFETY: GIANT is nonzero.
+                length: unsafe{ NonZeroUsize::new_unchecked((GIANT as usize) << SHIFT_BITS) },
+            };
         }
         let len = ((variant as usize) << SHIFT_BITS)  |  s.len();
-        Nbstr {
-            pointer: s.as_ptr(),
-            length: unsafe{ NonZero::new(len) },
+        NoGiants {
+            pointer: s as *const u8,
+            // SAFETY: variant is nonzero, and the length is too short to overlap it.
+            length: unsafe{ NonZeroUsize::new_unchecked(len) },
         }
     }
 
+    #[inline]
     fn variant(&self) -> u8 {
-        (*self.length >> SHIFT_BITS) as u8
+        match self.giant() {
+            Some(giant) => giant.variant(),
+            None => (self.length.get() >> SHIFT_BITS) as u8,
+        }
     }
     fn data(&mut self) -> &mut[u8] {
-        let arr: &mut[u8; SIZE] = unsafe{ mem::transmute(self) };
+        // SAFETY: NoGiants is repr(C) with two words and no padding, and u8 needs no alignment.
+        //  The variant byte is excluded below, so length stays nonzero.
+        let arr = unsafe{ &mut *(self as *mut NoGiants as *mut [u8; SIZE]) };
         if cfg!(target_endian="big") {
             &mut arr[1..]
         } else {
             &mut arr[..SIZE-1]
         }
     }
+    #[inline]
+    fn get_pointer(&self) -> *const [u8] {
+        match self.giant() {
+            Some(giant) => giant.get_pointer(),
+            None => ptr::slice_from_raw_parts(self.pointer,  self.length.get() & MAX_DIRECT_LENGTH),
+        }
+    }
+    #[inline]
     fn get_slice(&self) -> &[u8] {
         if self.variant() > MAX_STACK {
-            unsafe{ slice::from_raw_parts(self.pointer,  *self.length & MAX_LENGTH ) }
+            // SAFETY: the pointer is to 'static or 'a memory, or to a box or Arc owned by self.
+            unsafe{ &*self.get_pointer() }
         } else {
-            let arr: &[u8; SIZE] = unsafe{ mem::transmute( self )};
+            // SAFETY: as in data(), and inline strings have no pointer whose provenance would matter.
+            let arr = unsafe{ &*(self as *const NoGiants as *const [u8; SIZE]) };
             if cfg!(target_endian="little") {
                 &arr[..self.variant() as usize]
             } else {
@@ -118,4 +207,15 @@ impl Protected for Nbstr {
             }
         }
     }
+    #[inline]
+    fn inline_words(&self) -> [usize; 2] {
+        // SAFETY: two words, and inline strings store only bytes in the pointer.
+        let words: [usize; 2] = unsafe{ mem::transmute_copy(self) };
+        // remove the byte with the variant, and move the rest to the start if it's first.
+        if cfg!(target_endian="little") {
+            [words[0],  words[1] & (!0 >> 8)]
+        } else {
+            [(words[0] << 8) | (words[1] >> (8*(POINTER_BYTES-1))),  words[1] << 8]
+        }
+    }
 }
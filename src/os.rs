@@ -0,0 +1,294 @@
+/* Copyright 2016 Torbjørn Birch Moltu
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+// The encoded bytes of an `OsStr` are stored in the same representations as a `str`;
+// they are only turned back into an `OsStr` when borrowed or unwrapped.
+
+use Nbstr;
+use Repr;
+use stats;
+use shared::{Protected, copy_bytes,from_vec,take_boxed_bytes,variant_name};
+use std::cmp::Ordering;
+use std::ffi::{OsStr,OsString};
+use std::ops::Deref;
+use std::path::{Path,PathBuf};
+use std::{fmt,hash};
+use std::borrow::{Borrow,ToOwned};
+use std::boxed::Box;
+
+
+
+/// A lean `Cow<'static, OsStr>` that cannot be written to.
+#[derive(Clone, Default)]
+pub struct NbOsStr(Nbstr);// contains encoded bytes, which might not be UTF-8
+
+/// A lean `Cow<'static, Path>` that cannot be written to.
+///
+/// Is useful for storing many paths that won't be modified,
+/// and can be passed directly to `std::fs` and `std::process::Command`.
+#[derive(Clone, Default)]
+pub struct NbPath(NbOsStr);
+
+
+  //////////////////////////
+ //Encoded bytes handling//
+//////////////////////////
+
+fn as_os_str(z: &Nbstr) -> &OsStr {
+    // SAFETY: only NbOsStr::from_* and From impls create the bytes, from an OsStr or a str.
+    unsafe{ OsStr::from_encoded_bytes_unchecked(z.get_slice()) }
+}
+fn from_os_string(s: OsString) -> Nbstr {
+    from_vec(s.into_encoded_bytes())
+}
+fn into_os_string(mut z: Nbstr) -> OsString {
+    match take_boxed_bytes(&mut z) {
+        // SAFETY: the bytes came from an OsStr or a str.
+        Some(b) => unsafe{ OsString::from_encoded_bytes_unchecked(b.into_vec()) },
+        None => as_os_str(&z).to_owned(),
+    }
+}
+
+
+  ////////////////////
+ // public methods //
+////////////////////
+
+impl NbOsStr {
+    /// Store a `&'static OsStr` without copying it.
+    pub fn from_static(s: &'static OsStr) -> Self {
+        if !s.is_empty() {
+            stats::created(Repr::Static);
+        }
+        NbOsStr(Nbstr::with_pointer(Nbstr::LITERAL, s.as_encoded_bytes()))
+    }
+    /// Create a NbOsStr from a borrowed `OsStr` with a limited lifetime.
+    /// If it is short enough it will be stored the inside struct itself and not boxed.
+    pub fn from_os_str(s: &OsStr) -> Self {
+        NbOsStr(copy_bytes(s.as_encoded_bytes()))
+    }
+    /// Borrow the content as a `Path`.
+    pub fn as_path(&self) -> &Path {
+        Path::new(self.deref())
+    }
+}
+
+impl NbPath {
+    /// Store a `&'static Path` without copying it.
+    pub fn from_static(p: &'static Path) -> Self {
+        NbPath(NbOsStr::from_static(p.as_os_str()))
+    }
+    /// Create a NbPath from a borrowed `Path` with a limited lifetime.
+    /// If it is short enough it will be stored the inside struct itself and not boxed.
+    pub fn from_path(p: &Path) -> Self {
+        NbPath(NbOsStr::from_os_str(p.as_os_str()))
+    }
+    /// Borrow the content as an `OsStr`.
+    pub fn as_os_str(&self) -> &OsStr {
+        self.0.deref()
+    }
+}
+
+
+  ///////////////
+ //Conversions//
+///////////////
+
+impl From<&'static OsStr> for NbOsStr {
+    fn from(s: &'static OsStr) -> Self {
+        Self::from_static(s)
+    }
+}
+impl From<OsString> for NbOsStr {
+    fn from(s: OsString) -> Self {
+        NbOsStr(from_os_string(s))
+    }
+}
+impl From<Box<OsStr>> for NbOsStr {
+    fn from(s: Box<OsStr>) -> Self {
+        NbOsStr(from_os_string(s.into_os_string()))
+    }
+}
+/// Free, as a str is always a valid OsStr.
+impl From<Nbstr> for NbOsStr {
+    fn from(z: Nbstr) -> Self {
+        NbOsStr(z)
+    }
+}
+/// Free
+impl From<NbPath> for NbOsStr {
+    fn from(p: NbPath) -> Self {
+        p.0
+    }
+}
+
+impl From<&'static Path> for NbPath {
+    fn from(p: &'static Path) -> Self {
+        Self::from_static(p)
+    }
+}
+impl From<PathBuf> for NbPath {
+    fn from(p: PathBuf) -> Self {
+        NbPath(NbOsStr::from(p.into_os_string()))
+    }
+}
+impl From<Box<Path>> for NbPath {
+    fn from(p: Box<Path>) -> Self {
+        NbPath::from(p.into_path_buf())
+    }
+}
+/// Free, as a str is always a valid Path.
+impl From<Nbstr> for NbPath {
+    fn from(z: Nbstr) -> Self {
+        NbPath(NbOsStr(z))
+    }
+}
+/// Free
+impl From<NbOsStr> for NbPath {
+    fn from(s: NbOsStr) -> Self {
+        NbPath(s)
+    }
+}
+
+impl From<NbOsStr> for OsString {
+    fn from(s: NbOsStr) -> OsString {
+        into_os_string(s.0)
+    }
+}
+impl From<NbOsStr> for Box<OsStr> {
+    fn from(s: NbOsStr) -> Box<OsStr> {
+        into_os_string(s.0).into_boxed_os_str()
+    }
+}
+impl From<NbPath> for PathBuf {
+    fn from(p: NbPath) -> PathBuf {
+        PathBuf::from(into_os_string((p.0).0))
+    }
+}
+impl From<NbPath> for Box<Path> {
+    fn from(p: NbPath) -> Box<Path> {
+        PathBuf::from(p).into_boxed_path()
+    }
+}
+
+
+  ///////////
+ //Getters//
+///////////
+
+impl Deref for NbOsStr {
+    type Target = OsStr;
+    fn deref(&self) -> &OsStr {
+        as_os_str(&self.0)
+    }
+}
+impl AsRef<OsStr> for NbOsStr {
+    fn as_ref(&self) -> &OsStr {
+        self.deref()
+    }
+}
+impl AsRef<Path> for NbOsStr {
+    fn as_ref(&self) -> &Path {
+        self.as_path()
+    }
+}
+impl Borrow<OsStr> for NbOsStr {
+    fn borrow(&self) -> &OsStr {
+        self.deref()
+    }
+}
+
+impl Deref for NbPath {
+    type Target = Path;
+    fn deref(&self) -> &Path {
+        self.0.as_path()
+    }
+}
+impl AsRef<Path> for NbPath {
+    fn as_ref(&self) -> &Path {
+        self.deref()
+    }
+}
+impl AsRef<OsStr> for NbPath {
+    fn as_ref(&self) -> &OsStr {
+        self.as_os_str()
+    }
+}
+impl Borrow<Path> for NbPath {
+    fn borrow(&self) -> &Path {
+        self.deref()
+    }
+}
+
+
+  /////////////////
+ //Common traits//
+/////////////////
+
+impl hash::Hash for NbOsStr {
+    fn hash<H:hash::Hasher>(&self,  h: &mut H) {
+        self.deref().hash(h);
+    }
+}
+impl PartialOrd for NbOsStr {
+    fn partial_cmp(&self,  rhs: &Self) -> Option<Ordering> {
+        Some(self.cmp(rhs))
+    }
+}
+impl Ord for NbOsStr {
+    fn cmp(&self,  rhs: &Self) -> Ordering {
+        self.deref().cmp(rhs.deref())
+    }
+}
+impl PartialEq for NbOsStr {
+    fn eq(&self,  rhs: &Self) -> bool {
+        self.deref() == rhs.deref()
+    }
+} impl Eq for NbOsStr {}
+
+// Path compares and hashes by components, so these must go through Path and not OsStr.
+impl hash::Hash for NbPath {
+    fn hash<H:hash::Hasher>(&self,  h: &mut H) {
+        self.deref().hash(h);
+    }
+}
+impl PartialOrd for NbPath {
+    fn partial_cmp(&self,  rhs: &Self) -> Option<Ordering> {
+        Some(self.cmp(rhs))
+    }
+}
+impl Ord for NbPath {
+    fn cmp(&self,  rhs: &Self) -> Ordering {
+        self.deref().cmp(rhs.deref())
+    }
+}
+impl PartialEq for NbPath {
+    fn eq(&self,  rhs: &Self) -> bool {
+        self.deref() == rhs.deref()
+    }
+} impl Eq for NbPath {}
+
+/// Displays how the string is stored like Nbstr does, followed by the content like OsStr does.
+impl fmt::Debug for NbOsStr {
+    fn fmt(&self,  fmtr: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmtr, "{}: {:?}", variant_name(&self.0), self.deref())
+    }
+}
+/// Displays how the path is stored like Nbstr does, followed by the path like Path does.
+impl fmt::Debug for NbPath {
+    fn fmt(&self,  fmtr: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmtr, "{}: {:?}", variant_name(&(self.0).0), self.deref())
+    }
+}
@@ -0,0 +1,92 @@
+/* Copyright 2016 Torbjørn Birch Moltu
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use {Nbstr,NbCowIn,NbstrIn};
+use layouts::{Layout,Selected};
+use serde::{Serialize,Serializer, Deserialize,Deserializer};
+use serde::de::{Visitor,Error,Unexpected};
+use core::fmt;
+use core::marker::PhantomData;
+use core::ops::Deref;
+use core::str as Str;
+use alloc::string::String;
+
+
+impl<'a, L: Layout> Serialize for NbCowIn<'a, L> {
+    fn serialize<S:Serializer>(&self,  serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.deref())
+    }
+}
+
+
+/// Copies all strs, and doesn't keep the allocation of short Strings.
+struct CopyVisitor<L: Layout>(PhantomData<L>);
+impl<'de, L: Layout> Visitor<'de> for CopyVisitor<L> {
+    type Value = NbstrIn<L>;
+    fn expecting(&self,  fmtr: &mut fmt::Formatter) -> fmt::Result {
+        fmtr.write_str("a string")
+    }
+    fn visit_str<E:Error>(self,  s: &str) -> Result<NbstrIn<L>, E> {
+        Ok(NbstrIn::from_str(s))
+    }
+    fn visit_string<E:Error>(self,  s: String) -> Result<NbstrIn<L>, E> {
+        if s.len() <= NbstrIn::<L>::MAX_STACK {
+            Ok(NbstrIn::from_str(&s))// and drop s
+        } else {
+            Ok(NbstrIn::from(s))
+        }
+    }
+    fn visit_bytes<E:Error>(self,  b: &[u8]) -> Result<NbstrIn<L>, E> {
+        match Str::from_utf8(b) {
+            Ok(s) => self.visit_str(s),
+            Err(_) => Err(E::invalid_value(Unexpected::Bytes(b), &self)),
+        }
+    }
+}
+
+/// Stores strs borrowed from the input as literals.
+struct LiteralVisitor;
+impl Visitor<'static> for LiteralVisitor {
+    type Value = Nbstr;
+    fn expecting(&self,  fmtr: &mut fmt::Formatter) -> fmt::Result {
+        fmtr.write_str("a string")
+    }
+    fn visit_borrowed_str<E:Error>(self,  s: &'static str) -> Result<Nbstr, E> {
+        Ok(Nbstr::from(s))
+    }
+    fn visit_str<E:Error>(self,  s: &str) -> Result<Nbstr, E> {
+        CopyVisitor::<Selected>(PhantomData).visit_str(s)
+    }
+    fn visit_string<E:Error>(self,  s: String) -> Result<Nbstr, E> {
+        CopyVisitor::<Selected>(PhantomData).visit_string(s)
+    }
+    fn visit_bytes<E:Error>(self,  b: &[u8]) -> Result<Nbstr, E> {
+        CopyVisitor::<Selected>(PhantomData).visit_bytes(b)
+    }
+}
+
+impl<'de, 'a, L: Layout> Deserialize<'de> for NbCowIn<'a, L> {
+    fn deserialize<D:Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(CopyVisitor(PhantomData))
+    }
+}
+
+/// Deserialize from a `&'static str` (such as from `include_str!()`)
+///  and store strings that can be borrowed from the input as literals.
+///
+/// `Deserialize` cannot do this because it must work for input with any lifetime.
+pub fn deserialize_literal<D:Deserializer<'static>>(deserializer: D) -> Result<Nbstr, D::Error> {
+    deserializer.deserialize_str(LiteralVisitor)
+}
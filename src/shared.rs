@@ -13,29 +13,152 @@
  * limitations under the License.
  */
 
-use Nbstr;
-use nbstr::{MAX_LENGTH,MAX_STACK,LITERAL,BOX};
-extern crate std;
-use std::cmp::Ordering;
-use std::ops::Deref;
-use std::str as Str;
-use std::{mem,slice,ptr, fmt,hash};
-use std::borrow::{Borrow,Cow};
+use stats;
+use layouts::Layout;
+use split::{Split,SplitN,Lines,SplitWhitespace};
+#[cfg(feature="std")]
+use intern;
+use core::cmp::Ordering;
+use core::ops::{Add,Deref,RangeBounds};
+use core::str as Str;
+use core::{mem,ptr, error,fmt,hash};
+use core::marker::PhantomData;
+use core::borrow::Borrow;
+use alloc::borrow::{Cow,ToOwned};
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::str;
+use alloc::vec::Vec;
+#[cfg(target_has_atomic="ptr")]
+use alloc::sync::Arc;
 
 
 
 /// Protected methods used by the impls below.
 pub trait Protected {
-    /// create new of this variant with possibly uninitialized data
-    fn new(u8) -> Self;
-    /// store this str, which is either &'static or boxed
-    fn with_pointer(u8,  &str) -> Self;
+    /// 1...MAX_STACK => stack string with length n
+    const MAX_STACK: u8;
+    /// The longest str that can be stored in this layout.
+    const MAX_LENGTH: usize;
+    /// &'static str
+    const LITERAL: u8 = Self::MAX_STACK+1;
+    /// Box<str>
+    const BOX: u8 = Self::MAX_STACK+2;
+    /// Arc<str>
+    const SHARED: u8 = Self::MAX_STACK+3;
+    /// Arc<str> shared with the interner
+    const INTERNED: u8 = Self::MAX_STACK+4;
+    /// &'a str
+    const BORROWED: u8 = Self::MAX_STACK+5;
+    // empty strings are stored as LITERAL with non-NULL but possibly invalid pointer and zero length
+    //     (slices cannot have NULL pointers)
+
+    /// create new of this variant with zeroed data
+    fn new(variant: u8) -> Self;
+    /// store this str or slice, which is either borrowed, boxed or shared.
+    /// Takes a raw pointer so that boxes and Arcs keep the provenance they were created with,
+    ///  which a pointer that went through a `&[u8]` wouldn't have.
+    fn with_pointer(variant: u8,  s: *const [u8]) -> Self;
 
     fn variant(&self) -> u8;
     /// get the area of self where (length,pointer)|inline is.
     fn data(&mut self) -> &mut [u8];
+    /// the pointer given to with_pointer(); assumes non-stack.
+    /// Must be used instead of get_slice() to free or write to what it points to.
+    fn get_pointer(&self) -> *const [u8];
     /// the root of AsRef,Borrow and Deref.
     fn get_slice(&self) -> &[u8];
+    /// the inline bytes in order followed by zeroes, as two words; assumes stack.
+    /// The unused bytes are always zero, so these can be compared directly.
+    /// Only used if FITS_WORDS.
+    fn inline_words(&self) -> [usize; 2];
+    /// whether inline_words() contains all inline bytes
+    const FITS_WORDS: bool = Self::MAX_STACK as usize <= 2*mem::size_of::<usize>();
+}
+
+/// The real pointer, length and variant of a str that a layout cannot store directly,
+///  in an extra allocation that the layout points to with a variant of its own.
+/// The layout owns the allocation, but what it points to is owned by the NbCow.
+pub struct OutOfLine {
+    pointer: *const u8,
+    length: usize,
+    variant: u8,
+}
+impl OutOfLine {
+    /// must be freed with free() when the layout is dropped.
+    pub fn allocate(variant: u8,  s: *const [u8]) -> *mut OutOfLine {
+        let out_of_line = OutOfLine{ pointer: s as *const u8,  length: s.len(),  variant: variant };
+        Box::into_raw(Box::new(out_of_line))
+    }
+    /// Only frees the OutOfLine, not what it points to.
+    ///
+    /// # Safety
+    /// The pointer must come from allocate(), and not be used afterwards.
+    pub unsafe fn free(pointer: *mut OutOfLine) {
+        // SAFETY: allocate() got it from Box::into_raw(), and the caller promises it's only freed once.
+        drop(unsafe{ Box::from_raw(pointer) });
+    }
+    #[inline]
+    pub fn variant(&self) -> u8 {
+        self.variant
+    }
+    /// the pointer given to allocate()
+    #[inline]
+    pub fn get_pointer(&self) -> *const [u8] {
+        ptr::slice_from_raw_parts(self.pointer,  self.length)
+    }
+}
+
+
+/// A lean `Cow<'a, str>` that cannot be written to, stored with the layout `L`.
+///
+/// `NbCow<'a>` and `Nbstr` use the layout selected by cargo features;
+/// name the layout to get the same one regardless of which features other crates enable.
+/// See the `layouts` module.
+pub struct NbCowIn<'a, L: Layout> {
+    raw: L,
+    _borrowed: PhantomData<&'a str>,
+}
+
+impl<'a, L: Layout> NbCowIn<'a, L> {
+    pub(crate) const fn from_raw(raw: L) -> Self {
+        NbCowIn{ raw: raw,  _borrowed: PhantomData }
+    }
+    /// Give up ownership of the string without dropping it,
+    ///  but let the layout free anything it has allocated itself.
+    fn into_raw(self) -> L {
+        // SAFETY: self is forgotten, so raw is only dropped once.
+        let raw = unsafe{ ptr::read(&self.raw) };
+        mem::forget(self);
+        return raw;
+    }
+}
+
+// Lets the code below use the layout through NbCowIn.
+impl<'a, L: Layout> Protected for NbCowIn<'a, L> {
+    const MAX_STACK: u8 = L::MAX_STACK;
+    const MAX_LENGTH: usize = L::MAX_LENGTH;
+    fn new(variant: u8) -> Self {
+        Self::from_raw(L::new(variant))
+    }
+    fn with_pointer(variant: u8,  s: *const [u8]) -> Self {
+        Self::from_raw(L::with_pointer(variant, s))
+    }
+    fn variant(&self) -> u8 {
+        self.raw.variant()
+    }
+    fn data(&mut self) -> &mut [u8] {
+        self.raw.data()
+    }
+    fn get_pointer(&self) -> *const [u8] {
+        self.raw.get_pointer()
+    }
+    fn get_slice(&self) -> &[u8] {
+        self.raw.get_slice()
+    }
+    fn inline_words(&self) -> [usize; 2] {
+        self.raw.inline_words()
+    }
 }
 
 
@@ -44,25 +167,360 @@ pub trait Protected {
  // public methods //
 ////////////////////
 
-impl Nbstr {
-    #[cfg(feature="unstable")]
-    /// Get the max length a Nbstr can store,
-    ///  as some compile-time features might limit it.
-    pub const MAX_LENGTH: usize = MAX_LENGTH;
-
-    /// Get the max length a Nbstr can store,
-    ///  as some compile-time features might limit it.
+impl<'a, L: Layout> NbCowIn<'a, L> {
+    /// The max length a string can have with this layout.
     ///
-    /// This method will get deprecated once associated consts become stable.
+    /// Is `usize::MAX` for every layout, as they store strings that are too long
+    ///  for the struct itself through an extra allocation.
+    pub const MAX_LENGTH: usize = L::MAX_LENGTH;
+
+    /// Get the max length a string can have with this layout.
+    #[deprecated(note="use the MAX_LENGTH associated const")]
     pub fn max_length() -> usize {
-        MAX_LENGTH
+        L::MAX_LENGTH
+    }
+
+    /// The longest str that can be stored inside the struct itself with this layout.
+    pub const MAX_STACK: usize = L::MAX_STACK as usize;
+    /// The size of the struct with this layout.
+    pub const SIZE: usize = mem::size_of::<Self>();
+
+    /// Get how the string is stored.
+    pub fn repr(&self) -> Repr {
+        // associated consts cannot be used as patterns in generic code
+        let variant = self.variant();
+        if variant != 0  &&  variant <= L::MAX_STACK {Repr::Inline}
+        else if variant == L::LITERAL {Repr::Static}
+        else if variant == L::BOX {Repr::Heap}
+        else if variant == L::SHARED {Repr::Shared}
+        else if variant == L::INTERNED {Repr::Interned}
+        else if variant == L::BORROWED {Repr::Borrowed}
+        else {unreachable!("Unknown variant of NbCow: {}", variant)}
+    }
+    /// Check if the string is stored inside the struct itself.
+    pub fn is_inline(&self) -> bool {
+        self.repr() == Repr::Inline
+    }
+    /// Get the `&'static str` if that is how the string is stored.
+    pub fn as_static(&self) -> Option<&'static str> {
+        if self.variant() == L::LITERAL {
+            // SAFETY: the pointer is to 'static memory even if self isn't
+            Some(unsafe{ &*(self.deref() as *const str) })
+        } else {
+            None
+        }
+    }
+    /// Get the size of the heap allocation the string is stored in,
+    ///  or zero if it isn't stored in one.
+    ///
+    /// For shared and interned strings this includes the reference counts of the `Arc`,
+    ///  and the allocation is shared with other Nbstrs.
+    pub fn heap_size(&self) -> usize {
+        match self.repr() {
+            Repr::Heap => self.len(),
+            Repr::Shared | Repr::Interned => 2*mem::size_of::<usize>() + self.len(),
+            _ => 0,
+        }
+    }
+    /// Check if both strings are stored in the same memory, and not just equal.
+    ///
+    /// Strings stored inside the struct itself are only stored in the same memory as themselves.
+    pub fn ptr_eq(&self,  other: &Self) -> bool {
+        self.as_ptr() == other.as_ptr()  &&  self.len() == other.len()
     }
 
-    // keeping all public methods under one impl gives cleaner rustdoc
     /// Create a Nbstr from a borrowed str with a limited lifetime.
     /// If the str is short enough it will be stored the inside struct itself and not boxed.
     pub fn from_str(s: &str) -> Self {
-        Self::try_stack(s).unwrap_or_else(|| s.to_owned().into() )
+        copy_bytes(s.as_bytes())
+    }
+
+    /// Like `from_str()`, but returns an error if the str is longer than `MAX_LENGTH`.
+    ///
+    /// Never fails with the current layouts, which all can store strings of any length.
+    pub fn try_from_str(s: &str) -> Result<Self, NbstrError> {
+        check_length::<L>(s.len())?;
+        Ok(Self::from_str(s))
+    }
+    /// Like `From<String>`, but returns an error if the string is longer than `MAX_LENGTH`.
+    ///
+    /// Never fails with the current layouts; see `try_from_str()`.
+    pub fn try_from_string(s: String) -> Result<Self, NbstrError> {
+        check_length::<L>(s.len())?;
+        Ok(Self::from(s))
+    }
+    /// Like `From<Box<str>>`, but returns an error if the str is longer than `MAX_LENGTH`.
+    ///
+    /// Never fails with the current layouts; see `try_from_str()`.
+    pub fn try_from_boxed(s: Box<str>) -> Result<Self, NbstrError> {
+        check_length::<L>(s.len())?;
+        Ok(Self::from(s))
+    }
+
+    /// Get a Nbstr that shares its content with all other interned Nbstrs that are equal to s,
+    /// adding s to the global interner if it isn't there.
+    ///
+    /// Comparing two equal interned Nbstrs only compares pointers.
+    /// See the `intern` module for managing the interner.
+    #[cfg(feature="std")]
+    pub fn intern(s: &str) -> Self {
+        intern::intern(s)
+    }
+
+    /// Create a NbCow that borrows s instead of copying it, like `Cow::Borrowed`.
+    pub fn borrowed(s: &'a str) -> Self {
+        if s.is_empty() {
+            Self::default()
+        } else {
+            stats::created(Repr::Borrowed);
+            Self::with_pointer(L::BORROWED, s.as_bytes())
+        }
+    }
+
+    /// Concatenate strs into a single Nbstr.
+    ///
+    /// The total length is computed first, so that the result is stored in the struct itself
+    /// if short enough, or copied into an exactly-sized box with a single allocation.
+    /// Concatenating only empty strs returns an empty literal.
+    pub fn concat(parts: &[&str]) -> Self {
+        Self::join(parts, "")
+    }
+
+    /// Join strs with a separator into a single Nbstr.
+    ///
+    /// Like `concat()`, the total length is computed before anything is copied,
+    /// which is why the iterator must be `Clone`.
+    pub fn join<I,S>(parts: I,  separator: &str) -> Self
+    where I: IntoIterator<Item=S>,  I::IntoIter: Clone,  S: AsRef<str> {
+        let parts = parts.into_iter();
+        let mut len = 0;
+        for (i, part) in parts.clone().enumerate() {
+            len += part.as_ref().len();
+            if i != 0 {
+                len += separator.len();
+            }
+        }
+        if len == 0 {
+            return Self::default();
+        } else if len <= L::MAX_STACK as usize {
+            let mut z = Self::new(len as u8);
+            {
+                let data = z.data();
+                let mut end = 0;
+                for (i, part) in parts.enumerate() {
+                    if i != 0 {
+                        data[end..end+separator.len()].copy_from_slice(separator.as_bytes());
+                        end += separator.len();
+                    }
+                    let part = part.as_ref().as_bytes();
+                    data[end..end+part.len()].copy_from_slice(part);
+                    end += part.len();
+                }
+            }
+            stats::created(Repr::Inline);
+            return z;
+        }
+        let mut bytes = Vec::with_capacity(len);
+        for (i, part) in parts.enumerate() {
+            if i != 0 {
+                bytes.extend_from_slice(separator.as_bytes());
+            }
+            bytes.extend_from_slice(part.as_ref().as_bytes());
+        }
+        from_vec(bytes)
+    }
+
+    /// Get a part of the string as a new NbCow.
+    ///
+    /// Parts of literals and borrowed strs are not copied,
+    /// other parts are stored in the struct itself if short enough, or copied into a box.
+    ///
+    /// # Panics
+    /// If the range is out of bounds or not on char boundaries, like indexing a str.
+    pub fn slice<R:RangeBounds<usize>>(&self,  range: R) -> Self {
+        substring(self, &self.deref()[(range.start_bound().cloned(), range.end_bound().cloned())])
+    }
+    /// Get the string without leading and trailing whitespace, like `str::trim()`.
+    /// Doesn't copy literals and borrowed strs; see `slice()`.
+    pub fn trim(&self) -> Self {
+        substring(self, self.deref().trim())
+    }
+    /// Get the string without leading whitespace, like `str::trim_start()`.
+    pub fn trim_start(&self) -> Self {
+        substring(self, self.deref().trim_start())
+    }
+    /// Get the string without trailing whitespace, like `str::trim_end()`.
+    pub fn trim_end(&self) -> Self {
+        substring(self, self.deref().trim_end())
+    }
+    /// Split the string in two at a byte index, like `str::split_at()`.
+    /// Doesn't copy literals and borrowed strs; see `slice()`.
+    ///
+    /// # Panics
+    /// If `mid` is out of bounds or not on a char boundary.
+    pub fn split_at(&self,  mid: usize) -> (Self, Self) {
+        let (first, second) = self.deref().split_at(mid);
+        (substring(self, first), substring(self, second))
+    }
+    /// Get the rest of the string if it starts with `prefix`, like `str::strip_prefix()`.
+    pub fn strip_prefix(&self,  prefix: &str) -> Option<Self> {
+        self.deref().strip_prefix(prefix).map(|rest| substring(self, rest) )
+    }
+    /// Get the rest of the string if it ends with `suffix`, like `str::strip_suffix()`.
+    pub fn strip_suffix(&self,  suffix: &str) -> Option<Self> {
+        self.deref().strip_suffix(suffix).map(|rest| substring(self, rest) )
+    }
+
+    /// Iterate over the parts of the string separated by `separator`, like `str::split()`.
+    ///
+    /// Parts of literals and borrowed strs are not copied,
+    /// other parts are stored in the struct itself if short enough, or copied into a box.
+    pub fn split<'s>(&'s self,  separator: &'s str) -> Split<'s,'a,L> {
+        Split{ source: self,  parts: self.deref().split(separator) }
+    }
+    /// Iterate over at most `n` parts of the string separated by `separator`, like `str::splitn()`.
+    /// Doesn't copy literals and borrowed strs; see `split()`.
+    pub fn splitn<'s>(&'s self,  n: usize,  separator: &'s str) -> SplitN<'s,'a,L> {
+        SplitN{ source: self,  parts: self.deref().splitn(n, separator) }
+    }
+    /// Iterate over the lines of the string, like `str::lines()`.
+    /// Doesn't copy literals and borrowed strs; see `split()`.
+    pub fn lines<'s>(&'s self) -> Lines<'s,'a,L> {
+        Lines{ source: self,  lines: self.deref().lines() }
+    }
+    /// Iterate over the whitespace-separated words of the string, like `str::split_whitespace()`.
+    /// Doesn't copy literals and borrowed strs; see `split()`.
+    pub fn split_whitespace<'s>(&'s self) -> SplitWhitespace<'s,'a,L> {
+        SplitWhitespace{ source: self,  words: self.deref().split_whitespace() }
+    }
+
+    /// Copy borrowed and `&'static` strs into the struct itself or a box,
+    ///  so that the content is owned, like `Cow::into_owned()`.
+    pub fn into_owned(self) -> NbCowIn<'static, L> {
+        if self.variant() == L::BORROWED  ||  self.variant() == L::LITERAL {
+            NbCowIn::from_str(self.deref())
+        } else {
+            self.into_static()
+        }
+    }
+
+    /// Copy borrowed strs into the struct itself or a box, but keep `&'static str`s,
+    ///  so that nothing with a limited lifetime is borrowed.
+    pub fn into_static(self) -> NbCowIn<'static, L> {
+        if self.variant() == L::BORROWED {
+            NbCowIn::from_str(self.deref())
+        } else {
+            // SAFETY: no other variant borrows for 'a, and the lifetime doesn't affect layout.
+            unsafe{ mem::transmute::<NbCowIn<'a, L>, NbCowIn<'static, L>>(self) }
+        }
+    }
+
+    /// Leak the heap allocation of a boxed or shared string and store it as a literal,
+    ///  so that cloning it and converting it to a `Cow<'static, str>` is free.
+    ///
+    /// Strings stored inside the struct itself are kept there, as they are already cheap to clone,
+    ///  and borrowed strs are copied into the struct itself or a box that is then leaked.
+    /// This is not the same as `into_static()`, which keeps boxes and doesn't leak anything.
+    pub fn into_literal(mut self) -> NbCowIn<'static, L> {
+        if let Some(b) = take_box(&mut self) {
+            return NbCowIn::from(&*Box::leak(b));
+        }
+        #[cfg(target_has_atomic="ptr")]
+        {
+            if let Some(a) = take_shared(&mut self) {
+                // SAFETY: never decrementing the reference count keeps the memory alive
+                let s: &'static str = unsafe{ &*Arc::into_raw(a) };
+                return NbCowIn::from(s);
+            }
+        }
+        if self.variant() == L::BORROWED {
+            NbCowIn::from_str(&self).into_literal()
+        } else {// inline or already a literal
+            self.into_static()
+        }
+    }
+
+    /// Get a `&'static str` by leaking the heap allocation of a boxed or shared string.
+    ///
+    /// Strings stored inside the struct itself and borrowed strs are copied into a leaked box,
+    ///  and literals are returned unchanged.
+    pub fn leak(self) -> &'static str {
+        let z = self.into_literal();
+        match z.as_static() {
+            Some(s) => s,
+            None => Box::leak(Box::<str>::from(z)),
+        }
+    }
+
+    /// Store the string with another layout, keeping how it is stored without copying it,
+    ///  like `From` does between the layouts.
+    ///
+    /// Strings stored inside the struct itself are moved into a box if they are too long
+    ///  to be stored inside the new struct.
+    pub fn into_layout<M: Layout>(self) -> NbCowIn<'a, M> {
+        if self.variant() <= L::MAX_STACK {
+            return copy_bytes(self.get_slice());
+        }
+        // The other variants have the same order in all layouts.
+        let variant = self.variant() - L::MAX_STACK + M::MAX_STACK;
+        let z = NbCowIn::with_pointer(variant, self.get_pointer());
+        drop(self.into_raw());// z now owns any box or Arc
+        return z;
+    }
+}
+
+
+/// How a Nbstr stores its string, as returned by `NbCow::repr()`.
+#[derive(Clone,Copy, PartialEq,Eq, Debug)]
+#[non_exhaustive]
+pub enum Repr {
+    /// Inside the struct itself.
+    Inline,
+    /// As a `&'static str`.
+    Static,
+    /// In a `Box<str>` owned by the Nbstr.
+    Heap,
+    /// In an `Arc<str>`.
+    Shared,
+    /// In an `Arc<str>` that is also referenced by the global interner.
+    Interned,
+    /// As a `&'a str` with a limited lifetime.
+    Borrowed,
+}
+
+
+/// The error returned by `NbCow::try_from_str()` and the other fallible constructors.
+///
+/// Is never returned by the current layouts, as they all have `MAX_LENGTH == usize::MAX`.
+#[derive(Clone,Copy, PartialEq,Eq, Debug)]
+#[non_exhaustive]
+pub enum NbstrError {
+    /// The str is longer than the layout can store.
+    TooLong {
+        /// The length of the str.
+        len: usize,
+        /// `MAX_LENGTH` of the layout.
+        max: usize,
+    },
+}
+impl fmt::Display for NbstrError {
+    fn fmt(&self,  fmtr: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            NbstrError::TooLong{len, max} => write!(fmtr,
+                "a str with length {} is longer than the {} bytes this layout of Nbstr can store",
+                len, max
+            ),
+        }
+    }
+}
+impl error::Error for NbstrError {}
+
+// Always Ok for now, but keeps the fallible constructors honest if a layout gets a limit.
+fn check_length<L: Layout>(len: usize) -> Result<(), NbstrError> {
+    if len > L::MAX_LENGTH {
+        Err(NbstrError::TooLong{ len: len,  max: L::MAX_LENGTH })
+    } else {
+        Ok(())
     }
 }
 
@@ -71,72 +529,123 @@ impl Nbstr {
  //Constructors//
 ////////////////
 
-impl Default for Nbstr {
+impl<'a, L: Layout> Default for NbCowIn<'a, L> {
     fn default() -> Self {
-        let s = unsafe{ slice::from_raw_parts(1 as *const u8, 0) };//pointer must be nonzero
-        Self::with_pointer(LITERAL, unsafe{ mem::transmute(s) })
+        Self::with_pointer(L::LITERAL, b"")// pointer is nonzero
     }
 }
-impl From<&'static str> for Nbstr {
+impl<'a, L: Layout> From<&'static str> for NbCowIn<'a, L> {
     fn from(s: &'static str) -> Self {
-        Self::with_pointer(LITERAL, s)
+        if !s.is_empty() {
+            stats::created(Repr::Static);
+        }
+        Self::with_pointer(L::LITERAL, s.as_bytes())
     }
 }
-impl Nbstr {
-    fn try_stack(s: &str) -> Option<Self> {match s.len() as u8 {
-        // Cannot have stack str with length 0, as variant might be NonZero
-        0 => Some(Self::default()),
-        1...MAX_STACK => {
-            let mut z = Self::new(s.len() as u8);
-            for (d, s) in z.data().iter_mut().zip(s.bytes()) {
-                *d = s;
-            }
-            Some(z)
-        },
-        _ => None,
-    }}
+// The byte versions of constructors are also used by NbBytes.
+pub fn try_stack<'a, L: Layout>(s: &[u8]) -> Option<NbCowIn<'a, L>> {match s.len() {
+    // Cannot have stack str with length 0, as variant is NonZero
+    0 => Some(NbCowIn::default()),
+    len if len <= L::MAX_STACK as usize => {
+        let mut z = NbCowIn::new(s.len() as u8);
+        z.data()[..s.len()].copy_from_slice(s);
+        stats::created(Repr::Inline);
+        Some(z)
+    },
+    _ => None,
+}}
+pub fn copy_bytes<'a, L: Layout>(b: &[u8]) -> NbCowIn<'a, L> {
+    try_stack(b).unwrap_or_else(|| from_boxed_bytes(b.to_owned().into_boxed_slice()) )
+}
+pub fn from_boxed_bytes<'a, L: Layout>(b: Box<[u8]>) -> NbCowIn<'a, L> {
+    // Don't try stack; users might turn it back into a box later
+    if b.is_empty() {
+        return NbCowIn::default();// Make it clear we don't own any memory.
+    }
+    stats::created(Repr::Heap);
+    stats::boxed(b.len());
+    NbCowIn::with_pointer(L::BOX, Box::into_raw(b))
 }
-impl From<Box<str>> for Nbstr {
+pub fn from_vec<'a, L: Layout>(v: Vec<u8>) -> NbCowIn<'a, L> {
+    if v.capacity() != v.len() {// into_boxed will reallocate
+        if let Some(inline) = try_stack(&v) {
+            return inline;// and drop v
+        }
+        stats::string_reallocation();
+    }
+    return from_boxed_bytes(v.into_boxed_slice());
+}
+impl<'a, L: Layout> From<Box<str>> for NbCowIn<'a, L> {
     fn from(s: Box<str>) -> Self {
-        // Don't try stack; users might turn it back into a box later
-        let z = if s.is_empty() {Self::default()}// Make it clear we don't own any memory.
-                else {Self::with_pointer(BOX, &s)};
-        mem::forget(s);
-        return z;
+        from_boxed_bytes(s.into_boxed_bytes())
     }
 }
-impl From<String> for Nbstr {
+impl<'a, L: Layout> From<String> for NbCowIn<'a, L> {
     fn from(s: String) -> Self {
-        if s.capacity() != s.len() {// into_boxed will reallocate
-            if let Some(inline) = Self::try_stack(&s) {
-                return inline;// and drop s
-            }
+        from_vec(s.into_bytes())
+    }
+}
+#[cfg(target_has_atomic="ptr")]
+impl<'a, L: Layout> From<Arc<str>> for NbCowIn<'a, L> {
+    fn from(s: Arc<str>) -> Self {
+        // Don't try stack; users might turn it back into an Arc later
+        if s.is_empty() {
+            return Self::default();// and drop s
         }
-        return Self::from(s.into_boxed_str());
+        stats::created(Repr::Shared);
+        Self::with_pointer(L::SHARED, Arc::into_raw(s) as *const [u8])
     }
 }
-impl From<Cow<'static, str>> for Nbstr {
+impl<'a, L: Layout> From<Cow<'static, str>> for NbCowIn<'a, L> {
     fn from(cow: Cow<'static, str>) -> Self {match cow {
         Cow::Owned(owned) => Self::from(owned),
         Cow::Borrowed(borrowed) => Self::from(borrowed),
     }}
 }
 
-impl Clone for Nbstr {
+/// Copies both sides into the struct itself or a new exactly-sized box,
+///  unless `rhs` is empty, in which case `self` is returned unchanged.
+impl<'a,'b, L: Layout> Add<&'b str> for NbCowIn<'a, L> {
+    type Output = NbCowIn<'a, L>;
+    fn add(self,  rhs: &'b str) -> NbCowIn<'a, L> {
+        if rhs.is_empty() {
+            return self;// a literal stays a literal
+        }
+        Self::concat(&[&self, rhs])
+    }
+}
+
+impl<'a, L: Layout> Clone for NbCowIn<'a, L> {
     fn clone(&self) -> Self {
-        if self.variant() == BOX {// try stack
-            Nbstr::from_str(self.deref())
-        } else {// copy the un-copyable
+        if self.variant() == L::BOX {// try stack
+            let clone = copy_bytes(self.get_slice());
+            if clone.variant() == L::BOX {
+                stats::allocating_clone();
+            }
+            clone
+        } else if self.variant() == L::SHARED  ||  self.variant() == L::INTERNED {
+            // SAFETY: the pointer came from Arc::into_raw(), and self owns one of the counts,
+            //  so the Arc is alive. The new count is owned by the clone.
+            #[cfg(target_has_atomic="ptr")]
+            unsafe{ Arc::increment_strong_count(self.get_pointer() as *const str) };
+            Self::with_pointer(self.variant(), self.get_pointer())
+        } else if self.variant() > L::MAX_STACK {
+            // the layout might need to allocate
+            Self::with_pointer(self.variant(), self.get_pointer())
+        } else {
+            // SAFETY: inline strings own nothing, so a bitwise copy is an independent value.
             unsafe{ ptr::read(self) }
         }
     }
     fn clone_from(&mut self,  from: &Self) {
         // keep existing box if possible
-        if self.variant() == BOX  &&  self.len() == from.len() {
-            unsafe{ ptr::copy_nonoverlapping( from.as_ptr(),
-                                              mem::transmute(self.as_ptr()),
-                                              self.len()
-                                             )};
+        let (to, from_slice) = (self.get_slice(), from.get_slice());
+        if self.variant() == L::BOX  &&  to.len() == from_slice.len() {
+            // SAFETY: the pointer came from Box::into_raw() and is uniquely owned by self,
+            //  so it may be written through, unlike the shared reference to.
+            //  from cannot point into the box, as self is borrowed mutably.
+            let to = self.get_pointer() as *mut u8;
+            unsafe{ ptr::copy_nonoverlapping(from_slice.as_ptr(),  to,  from_slice.len()) };
         } else {
             *self = from.clone();
         }
@@ -148,29 +657,30 @@ impl Clone for Nbstr {
  //Getters//
 ///////////
 
-impl AsRef<[u8]> for Nbstr {
+impl<'a, L: Layout> AsRef<[u8]> for NbCowIn<'a, L> {
     fn as_ref(&self) -> &[u8] {
         self.get_slice()
     }
 }
-impl AsRef<str> for Nbstr {
+impl<'a, L: Layout> AsRef<str> for NbCowIn<'a, L> {
     fn as_ref(&self) -> &str {
         let bytes: &[u8] = self.as_ref();
+        // SAFETY: only strs are stored; NbBytes doesn't use this impl.
         unsafe{ Str::from_utf8_unchecked( bytes )}
     }
 }
-impl Deref for Nbstr {
+impl<'a, L: Layout> Deref for NbCowIn<'a, L> {
     type Target = str;
     fn deref(&self) -> &Self::Target {
         self.as_ref()
     }
 }
-impl Borrow<[u8]> for Nbstr {
+impl<'a, L: Layout> Borrow<[u8]> for NbCowIn<'a, L> {
     fn borrow(&self) -> &[u8] {
         self.as_ref()
     }
 }
-impl Borrow<str> for Nbstr {
+impl<'a, L: Layout> Borrow<str> for NbCowIn<'a, L> {
     fn borrow(&self) -> &str {
         self.as_ref()
     }
@@ -181,41 +691,69 @@ impl Borrow<str> for Nbstr {
  //Common traits//
 /////////////////
 
-impl hash::Hash for Nbstr {
+impl<'a, L: Layout> hash::Hash for NbCowIn<'a, L> {
+    // Must be the same as for str; there is no shortcut for interned strings.
     fn hash<H:hash::Hasher>(&self,  h: &mut H) {
         self.deref().hash(h);
     }
 }
-impl fmt::Display for Nbstr {
+impl<'a, L: Layout> fmt::Display for NbCowIn<'a, L> {
     fn fmt(&self,  fmtr: &mut fmt::Formatter) -> fmt::Result {
         fmt::Display::fmt(self.deref(), fmtr)
     }
 }
-impl PartialOrd for Nbstr {
+impl<'a, L: Layout> PartialOrd for NbCowIn<'a, L> {
     fn partial_cmp(&self,  rhs: &Self) -> Option<Ordering> {
-        self.deref().partial_cmp(rhs.deref())
+        Some(self.cmp(rhs))
     }
 }
-impl Ord for Nbstr {
+// Shortcuts only pay off for eq; with them cmp was slower than for str on some layouts.
+impl<'a, L: Layout> Ord for NbCowIn<'a, L> {
     fn cmp(&self,  rhs: &Self) -> Ordering {
         self.deref().cmp(rhs.deref())
     }
 }
-impl PartialEq for Nbstr {
+impl<'a, L: Layout> PartialEq for NbCowIn<'a, L> {
+    #[inline]
     fn eq(&self,  rhs: &Self) -> bool {
-        self.deref() == rhs.deref()
+        if L::FITS_WORDS  &&  self.variant() <= L::MAX_STACK  &&  rhs.variant() <= L::MAX_STACK {
+            // Unused bytes are zero, and the variant is the length.
+            return self.variant() == rhs.variant()  &&  self.inline_words() == rhs.inline_words();
+        }
+        let (l, r) = (self.get_slice(), rhs.get_slice());
+        // the same literal, Arc or interned string
+        (l.as_ptr() == r.as_ptr()  &&  l.len() == r.len())  ||  l == r
     }
-} impl Eq for Nbstr {}
+} impl<'a, L: Layout> Eq for NbCowIn<'a, L> {}
 
-/// Displays how the string is stored by prepending "stack: ", "literal: " or "boxed: ".
-impl fmt::Debug for Nbstr {
+/// Displays how the string is stored by prepending
+///  "stack: ", "literal: ", "boxed: ", "shared: ", "interned: " or "borrowed: ".
+impl<'a, L: Layout> fmt::Debug for NbCowIn<'a, L> {
     fn fmt(&self,  fmtr: &mut fmt::Formatter) -> fmt::Result {
-        write!(fmtr, "{}: {}", match self.variant() {
-            1...MAX_STACK => "stack",
-            LITERAL => "literal",
-            BOX => "boxed",
-            _ => unreachable!("Unknown variant of Nbstr: {}", self.variant())
-        }, self.deref())
+        write!(fmtr, "{}: {}", variant_name(self), self.deref())
+    }
+}
+/// Used by methods that return parts of z; part must point into z.
+pub fn substring<'a, L: Layout>(z: &NbCowIn<'a, L>,  part: &str) -> NbCowIn<'a, L> {
+    if part.is_empty() {
+        NbCowIn::default()
+    } else if z.variant() == L::LITERAL  ||  z.variant() == L::BORROWED {
+        // points to the same 'static or 'a memory as z does
+        stats::created(z.repr());
+        NbCowIn::with_pointer(z.variant(), part.as_bytes())
+    } else {// the memory is owned by z
+        copy_bytes(part.as_bytes())
+    }
+}
+/// Also used by NbBytes
+pub fn variant_name<'a, L: Layout>(z: &NbCowIn<'a, L>) -> &'static str {
+    match z.repr() {
+        Repr::Inline => "stack",
+        Repr::Static => "literal",
+        Repr::Heap => "boxed",
+        Repr::Shared => "shared",
+        Repr::Interned => "interned",
+        Repr::Borrowed => "borrowed",
     }
 }
 
@@ -225,39 +763,59 @@ impl fmt::Debug for Nbstr {
 ///////////////
 
 /// Returns Some if z contains a Box
-pub fn take_box(z: &mut Nbstr) -> Option<Box<str>> {
-    if z.variant() == BOX {
-        // I asked on #rust, and transmuting from & to mut is apparently undefined behaviour.
-        // Is it really in this case?
-        let s: *mut str = unsafe{ mem::transmute(z.get_slice()) };
+pub fn take_box<'a, L: Layout>(z: &mut NbCowIn<'a, L>) -> Option<Box<str>> {
+    // SAFETY: only strs are stored; NbBytes uses take_boxed_bytes().
+    take_boxed_bytes(z).map(|b| unsafe{ str::from_boxed_utf8_unchecked(b) })
+}
+/// The byte version of take_box(), used by NbBytes and Drop.
+pub fn take_boxed_bytes<'a, L: Layout>(z: &mut NbCowIn<'a, L>) -> Option<Box<[u8]>> {
+    if z.variant() == L::BOX {
+        // The pointer from Box::into_raw(), which unlike one from get_slice() may be freed.
+        let s = z.get_pointer() as *mut [u8];
+        stats::unboxed(s.len());
         // Cannot just assign default; then rust tries to drop the previous value!
         //  .. which then calls this function.
-        mem::forget(mem::replace(z,  Nbstr::default()));
+        drop(mem::take(z).into_raw());
+        // SAFETY: z no longer refers to the box, so the Box is the only owner.
         Some(unsafe{ Box::from_raw(s) })
     } else {
         None
     }
 }
 
-impl From<Nbstr> for Box<str> {
-    fn from(mut z: Nbstr) -> Box<str> {
+/// Returns Some if z contains an Arc, which might be shared with the interner.
+#[cfg(target_has_atomic="ptr")]
+pub fn take_shared<'a, L: Layout>(z: &mut NbCowIn<'a, L>) -> Option<Arc<str>> {
+    if z.variant() == L::SHARED  ||  z.variant() == L::INTERNED {
+        let s = z.get_pointer() as *const str;
+        drop(mem::take(z).into_raw());
+        // SAFETY: the pointer came from Arc::into_raw(), and the count z owned is moved to the Arc.
+        Some(unsafe{ Arc::from_raw(s) })
+    } else {
+        None
+    }
+}
+
+impl<'a, L: Layout> From<NbCowIn<'a, L>> for Box<str> {
+    fn from(mut z: NbCowIn<'a, L>) -> Box<str> {
         take_box(&mut z).unwrap_or_else(|| z.deref().to_owned().into_boxed_str() )
     }
 }
-impl From<Nbstr> for String {
-    fn from(mut z: Nbstr) -> String {
+impl<'a, L: Layout> From<NbCowIn<'a, L>> for String {
+    fn from(mut z: NbCowIn<'a, L>) -> String {
         take_box(&mut z)
             .map(|b| b.into_string() )
             .unwrap_or_else(|| z.deref().to_owned() )
     }
 }
-impl From<Nbstr> for Cow<'static, str> {
-    fn from(mut z: Nbstr) -> Cow<'static, str> {
+impl<'a, L: Layout> From<NbCowIn<'a, L>> for Cow<'a, str> {
+    fn from(mut z: NbCowIn<'a, L>) -> Cow<'a, str> {
         take_box(&mut z)
             .map(|b| Cow::from(b.into_string()) )
             .unwrap_or_else(||
-                if z.variant() == LITERAL {
-                    let s: &'static str = unsafe{ mem::transmute(z.deref()) };
+                if z.variant() == L::LITERAL  ||  z.variant() == L::BORROWED {
+                    // SAFETY: points to 'static or 'a memory, which outlives z.
+                    let s: &'a str = unsafe{ mem::transmute::<&str, &'a str>(z.deref()) };
                     Cow::from(s)
                 } else {
                     Cow::from(z.deref().to_owned())
@@ -265,11 +823,19 @@ impl From<Nbstr> for Cow<'static, str> {
              )
     }
 }
+#[cfg(target_has_atomic="ptr")]
+impl<'a, L: Layout> From<NbCowIn<'a, L>> for Arc<str> {
+    fn from(mut z: NbCowIn<'a, L>) -> Arc<str> {
+        take_shared(&mut z).unwrap_or_else(|| Arc::from(z.deref()) )
+    }
+}
 #[cfg(not(test))]// Bugs in drop might cause stack overflow in suprising places.
                 //  The tests below should catch said bugs.
-impl Drop for Nbstr {
+impl<'a, L: Layout> Drop for NbCowIn<'a, L> {
     fn drop(&mut self) {
-        let _ = take_box(self);
+        let _ = take_boxed_bytes(self);
+        #[cfg(target_has_atomic="ptr")]
+        let _ = take_shared(self);
     }
 }
 
@@ -281,11 +847,17 @@ impl Drop for Nbstr {
 
 #[cfg(test)]
 mod tests {
-    use nbstr::{Nbstr, MAX_STACK};
+    use {Nbstr,NbstrIn};
+    use layouts::{Wide,NoGiants};
+    #[cfg(all(target_arch="x86_64", not(miri)))]
+    use layouts::Packed48;
+    use no_giants::MAX_DIRECT_LENGTH;
     use super::*;
+    use std::prelude::v1::*;
     use std::ops::Deref;
     use std::str as Str;
-    use std::{mem,slice};
+    use std::ptr;
+    use std::sync::Arc;
 
     const STR: &'static str = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
 
@@ -320,20 +892,77 @@ mod tests {
         assert_eq!(take_box(&mut Nbstr::from_str(STR)), Some(b2.clone()));
     }
 
+    #[test]
+    fn shared() {
+        let a: Arc<str> = Arc::from(STR);
+        let mut z = Nbstr::from(a.clone());
+        assert_eq!(z.deref().as_ptr(), a.as_ptr());
+        assert_eq!(z.deref(), STR);
+        assert_eq!(Arc::strong_count(&a), 2);
+        assert_eq!(take_box(&mut z), None);
+        let b = take_shared(&mut z).unwrap();
+        assert!(Arc::ptr_eq(&a, &b));
+        assert_eq!(Arc::strong_count(&a), 2);
+        assert_eq!(take_shared(&mut z), None);
+        assert_eq!(take_shared(&mut Nbstr::from_str(STR)), None);
+    }
+
     #[test]
     fn nuls() {// Is here because MAX_STACK
-        let zeros_bytes = [0; MAX_STACK as usize];
+        let zeros_bytes = [0; Nbstr::MAX_STACK];
         let zeros_str = Str::from_utf8(&zeros_bytes).unwrap();
         let zeros = Nbstr::from_str(zeros_str);
         assert_eq!(zeros.deref(), zeros_str);
         assert!(Some(zeros).is_some());
     }
     #[test]
-    #[cfg_attr(debug_assertions, should_panic)]// from arithmetic overflow or explicit panic
-    fn too_long() {// Is here because no_giants has a custom panic message for tests,
-                  //   because the normal one would segfault on the invalid test str.
-        let b: &[u8] = unsafe{slice::from_raw_parts(1 as *const u8,  1+Nbstr::max_length() )};
-        let s: &'static str = unsafe{ mem::transmute(b) };
-        mem::forget(Nbstr::from(s));
+    fn fallible_constructors() {
+        assert_eq!(NbstrIn::<NoGiants>::try_from_str("fits").as_deref(), Ok("fits"));
+        let s = String::from("a str that is too long to be stored inline");
+        assert_eq!(NbstrIn::<NoGiants>::try_from_string(s.clone()).as_deref(), Ok(&s[..]));
+        let b = s.clone().into_boxed_str();
+        assert_eq!(NbstrIn::<Wide>::try_from_boxed(b).as_deref(), Ok(&s[..]));
+    }
+    /// A str that is too long to be stored directly, which must never be read,
+    ///  or even referenced, as nothing is allocated for it.
+    fn unread(len: usize) -> *const [u8] {
+        ptr::slice_from_raw_parts(ptr::dangling::<u8>(),  len)
+    }
+    fn parts(s: *const [u8]) -> (*const u8, usize) {
+        (s as *const u8,  s.len())
+    }
+    /// Checks that the pointer comes back unchanged, without dereferencing it.
+    fn check_out_of_line<L: Layout>(s: *const [u8]) {
+        for &(variant, repr) in &[(L::LITERAL, Repr::Static), (L::BORROWED, Repr::Borrowed)] {
+            let raw = L::with_pointer(variant, s);
+            assert_eq!((raw.variant(), parts(raw.get_pointer())), (variant, parts(s)));
+            let z = NbCowIn::from_raw(raw);
+            assert_eq!(z.repr(), repr);
+            let clone = z.clone();
+            assert_eq!((clone.repr(), parts(clone.get_pointer())), (repr, parts(s)));
+            assert!(clone == z);// same pointer and length, so the content isn't compared
+            let wide = z.into_layout::<Wide>();
+            assert_eq!((wide.repr(), parts(wide.get_pointer())), (repr, parts(s)));
+            let back = wide.into_layout::<L>();
+            assert_eq!((back.repr(), parts(back.get_pointer())), (repr, parts(s)));
+        }
+    }
+    #[test]
+    fn giant() {// NoGiants can only have real giants on 16- and 32-bit targets.
+        check_out_of_line::<NoGiants>(unread(1+MAX_DIRECT_LENGTH));
+    }
+    #[test]
+    #[cfg(target_pointer_width="64")]
+    fn len32_out_of_line() {
+        use layouts::Len32;
+        check_out_of_line::<Len32>(unread(1+0xffff_ffff));
+    }
+    #[test]
+    #[cfg(all(target_arch="x86_64", not(miri)))]// Packed48 uses exposed provenance
+    fn out_of_line() {
+        check_out_of_line::<Packed48>(unread(1+0x0000_ffff_ffff_ffff));
+        // as on a 57-bit address space
+        check_out_of_line::<Packed48>(ptr::slice_from_raw_parts(ptr::without_provenance(1 << 50),  0));
+        assert!(Packed48::address_bits() >= 48);
     }
 }
@@ -0,0 +1,101 @@
+/* Copyright 2016 Torbjørn Birch Moltu
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+// Wraps the str iterators, and turns the parts they return into NbCows.
+
+use shared::{NbCowIn,substring};
+use layouts::{Layout,Selected};
+use core::str as Str;
+use core::fmt;
+
+
+/// Iterator returned by `NbCow::split()`.
+pub struct Split<'s,'a:'s, L: Layout=Selected> {
+    pub(crate) source: &'s NbCowIn<'a, L>,
+    pub(crate) parts: Str::Split<'s, &'s str>,
+}
+impl<'s,'a, L: Layout> Iterator for Split<'s,'a,L> {
+    type Item = NbCowIn<'a, L>;
+    fn next(&mut self) -> Option<NbCowIn<'a, L>> {
+        self.parts.next().map(|part| substring(self.source, part) )
+    }
+}
+
+/// Iterator returned by `NbCow::splitn()`.
+pub struct SplitN<'s,'a:'s, L: Layout=Selected> {
+    pub(crate) source: &'s NbCowIn<'a, L>,
+    pub(crate) parts: Str::SplitN<'s, &'s str>,
+}
+impl<'s,'a, L: Layout> Iterator for SplitN<'s,'a,L> {
+    type Item = NbCowIn<'a, L>;
+    fn next(&mut self) -> Option<NbCowIn<'a, L>> {
+        self.parts.next().map(|part| substring(self.source, part) )
+    }
+}
+
+/// Iterator returned by `NbCow::lines()`.
+pub struct Lines<'s,'a:'s, L: Layout=Selected> {
+    pub(crate) source: &'s NbCowIn<'a, L>,
+    pub(crate) lines: Str::Lines<'s>,
+}
+impl<'s,'a, L: Layout> Iterator for Lines<'s,'a,L> {
+    type Item = NbCowIn<'a, L>;
+    fn next(&mut self) -> Option<NbCowIn<'a, L>> {
+        self.lines.next().map(|line| substring(self.source, line) )
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.lines.size_hint()
+    }
+}
+impl<'s,'a, L: Layout> DoubleEndedIterator for Lines<'s,'a,L> {
+    fn next_back(&mut self) -> Option<NbCowIn<'a, L>> {
+        self.lines.next_back().map(|line| substring(self.source, line) )
+    }
+}
+
+/// Iterator returned by `NbCow::split_whitespace()`.
+pub struct SplitWhitespace<'s,'a:'s, L: Layout=Selected> {
+    pub(crate) source: &'s NbCowIn<'a, L>,
+    pub(crate) words: Str::SplitWhitespace<'s>,
+}
+impl<'s,'a, L: Layout> Iterator for SplitWhitespace<'s,'a,L> {
+    type Item = NbCowIn<'a, L>;
+    fn next(&mut self) -> Option<NbCowIn<'a, L>> {
+        self.words.next().map(|word| substring(self.source, word) )
+    }
+}
+impl<'s,'a, L: Layout> DoubleEndedIterator for SplitWhitespace<'s,'a,L> {
+    fn next_back(&mut self) -> Option<NbCowIn<'a, L>> {
+        self.words.next_back().map(|word| substring(self.source, word) )
+    }
+}
+
+// derive would require L: Clone and L: Debug
+macro_rules! clone_and_debug {($($iter:ident: $inner:ident),+) => {$(
+    impl<'s,'a, L: Layout> Clone for $iter<'s,'a,L> {
+        fn clone(&self) -> Self {
+            $iter{ source: self.source,  $inner: self.$inner.clone() }
+        }
+    }
+    impl<'s,'a, L: Layout> fmt::Debug for $iter<'s,'a,L> {
+        fn fmt(&self,  fmtr: &mut fmt::Formatter) -> fmt::Result {
+            fmtr.debug_struct(stringify!($iter))
+                .field("source", self.source)
+                .field(stringify!($inner), &self.$inner)
+                .finish()
+        }
+    }
+)+}}
+clone_and_debug!{Split: parts, SplitN: parts, Lines: lines, SplitWhitespace: words}
@@ -0,0 +1,157 @@
+/* Copyright 2016 Torbjørn Birch Moltu
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Global counters for how Nbstrs are stored, enabled by the stats feature.
+//!
+//! The counters are updated with relaxed atomic operations,
+//! so a snapshot taken while other threads create Nbstrs might be slightly inconsistent.
+//! Nbstrs created in `const`s and `static`s are not counted.
+
+// Without the feature, the counting functions do nothing and the module is private.
+
+#[cfg(feature="stats")]
+use {NbCowIn,layouts::Layout};
+use Repr;
+#[cfg(feature="stats")]
+use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+
+#[cfg(feature="stats")]
+static CREATED: [AtomicUsize; 6] = [const{ AtomicUsize::new(0) }; 6];
+#[cfg(feature="stats")]
+static ALLOCATING_CLONES: AtomicUsize = AtomicUsize::new(0);
+#[cfg(feature="stats")]
+static LIVE_BOXED_BYTES: AtomicUsize = AtomicUsize::new(0);
+#[cfg(feature="stats")]
+static STRING_REALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+
+  ///////////////////////////////////
+ // Used where Nbstrs are created //
+///////////////////////////////////
+
+#[cfg(feature="stats")]
+pub(crate) fn created(repr: Repr) {
+    CREATED[repr as usize].fetch_add(1, Relaxed);
+}
+#[cfg(feature="stats")]
+pub(crate) fn allocating_clone() {
+    ALLOCATING_CLONES.fetch_add(1, Relaxed);
+}
+#[cfg(feature="stats")]
+pub(crate) fn boxed(bytes: usize) {
+    LIVE_BOXED_BYTES.fetch_add(bytes, Relaxed);
+}
+#[cfg(feature="stats")]
+pub(crate) fn unboxed(bytes: usize) {
+    LIVE_BOXED_BYTES.fetch_sub(bytes, Relaxed);
+}
+#[cfg(feature="stats")]
+pub(crate) fn string_reallocation() {
+    STRING_REALLOCATIONS.fetch_add(1, Relaxed);
+}
+
+#[cfg(not(feature="stats"))]
+#[inline(always)]
+pub(crate) fn created(_: Repr) {}
+#[cfg(not(feature="stats"))]
+#[inline(always)]
+pub(crate) fn allocating_clone() {}
+#[cfg(not(feature="stats"))]
+#[inline(always)]
+pub(crate) fn boxed(_: usize) {}
+#[cfg(not(feature="stats"))]
+#[inline(always)]
+pub(crate) fn unboxed(_: usize) {}
+#[cfg(not(feature="stats"))]
+#[inline(always)]
+pub(crate) fn string_reallocation() {}
+
+
+  ////////////////
+ // Public API //
+////////////////
+
+/// The number of Nbstrs stored in each way.
+#[cfg(feature="stats")]
+#[derive(Clone,Copy, Default, PartialEq,Eq, Debug)]
+pub struct Histogram {
+    /// Stored inside the struct itself.
+    pub inline: usize,
+    /// Stored as a `&'static str`.
+    pub literal: usize,
+    /// Stored in a `Box<str>`.
+    pub boxed: usize,
+    /// Stored in an `Arc<str>`.
+    pub shared: usize,
+    /// Stored in an `Arc<str>` shared with the interner.
+    pub interned: usize,
+    /// Stored as a `&'a str`.
+    pub borrowed: usize,
+}
+#[cfg(feature="stats")]
+impl Histogram {
+    fn count(&mut self,  repr: Repr) -> &mut usize {
+        match repr {
+            Repr::Inline => &mut self.inline,
+            Repr::Static => &mut self.literal,
+            Repr::Heap => &mut self.boxed,
+            Repr::Shared => &mut self.shared,
+            Repr::Interned => &mut self.interned,
+            Repr::Borrowed => &mut self.borrowed,
+        }
+    }
+}
+
+/// The values of the global counters at some point in time.
+#[cfg(feature="stats")]
+#[derive(Clone,Copy, Default, PartialEq,Eq, Debug)]
+pub struct Snapshot {
+    /// How many Nbstrs have been created in each way.
+    /// Empty strings are not counted.
+    pub created: Histogram,
+    /// The number of clones that had to copy the string into a new box.
+    pub allocating_clones: usize,
+    /// The combined length of all boxes currently owned by Nbstrs.
+    pub live_boxed_bytes: usize,
+    /// The number of `String`s or `Vec`s that had to be reallocated to fit exactly in a box.
+    pub string_reallocations: usize,
+}
+
+/// Read all the global counters.
+#[cfg(feature="stats")]
+pub fn snapshot() -> Snapshot {
+    let mut created = Histogram::default();
+    for &repr in &[Repr::Inline, Repr::Static, Repr::Heap, Repr::Shared, Repr::Interned, Repr::Borrowed] {
+        *created.count(repr) = CREATED[repr as usize].load(Relaxed);
+    }
+    Snapshot {
+        created: created,
+        allocating_clones: ALLOCATING_CLONES.load(Relaxed),
+        live_boxed_bytes: LIVE_BOXED_BYTES.load(Relaxed),
+        string_reallocations: STRING_REALLOCATIONS.load(Relaxed),
+    }
+}
+
+/// Count how the given strings are stored, without using the global counters.
+///
+/// Empty strings are counted as literals.
+#[cfg(feature="stats")]
+pub fn histogram<'a, L: Layout>(strings: &[NbCowIn<'a, L>]) -> Histogram {
+    let mut histogram = Histogram::default();
+    for z in strings {
+        *histogram.count(z.repr()) += 1;
+    }
+    return histogram;
+}
@@ -13,113 +13,205 @@
  * limitations under the License.
  */
 
-use shared::Protected;
-extern crate std;
-use std::{mem,slice};
-extern crate core;
-use self::core::nonzero::NonZero;
+use shared::{NbCowIn,Protected,OutOfLine};
+use core::{mem,ptr};
+use core::num::NonZeroU8;
+use core::arch::x86_64::__cpuid;
 
 
-pub const MAX_LENGTH: usize = 0x0000_ffff_ffff_ffff;
-#[allow(dead_code)]
-/// NonZero, never used
-pub const NONE: u8 = 0;
+const MAX_48BIT: usize = 0x0000_ffff_ffff_ffff;
 /// 1...12 => stack string with length n
-pub const MAX_STACK: u8 = 12;
-/// &'static str: 48bit pointer, 48bit size
-pub const LITERAL: u8 = MAX_STACK+1;
-/// Box<str>: 48bit pointer, 48bit size
-pub const BOX: u8 = MAX_STACK+2;
-// empty strings are stored as LITERAL with non-NULL but possibly invalid pointer and zero length
-//     (slices cannot have NULL pointers)
+const MAX_STACK: u8 = 12;
+// The other variants follow MAX_STACK and store a 48bit size followed by a 48bit pointer;
+//  see Protected.
+/// Stores the full 64bit pointer to an OutOfLine, which has the real variant.
+const OUT_OF_LINE: u8 = MAX_STACK+6;
 
 
-const MORE_THAN_48_BITS : &'static str =
-"Your systems implementation of x86_64 can use more than 48 bits of its pointers;\
- Please inform the maintainers of the 'nbstr' crate.\
- In the meantime, you can stop using the 64as48bit_hack feature.";
 
-
-
-/// A lean `Cow<'static, str>` that cannot be written to.
-#[unsafe_no_drop_flag]// is set to empty literal during drop()
-pub struct Nbstr {
-    variant: NonZero<u8>,
+/// Stores the lower 48 bits of pointer and length, which is enough on most x86_64 systems.
+///
+/// Is 13 bytes, but `from_static()` cannot be `const`.
+/// Pointers and lengths that don't fit in 48 bits, which can happen with 5-level paging,
+/// are stored through an extra allocation; `Packed48::address_bits()` tells whether that can happen.
+///
+/// Splitting pointers into bytes loses their provenance, so this layout exposes it
+/// and gets it back with `ptr::with_exposed_provenance()`.
+/// It is therefore not compatible with strict provenance, and cannot be checked by Miri
+/// with `-Zmiri-strict-provenance`.
+pub struct Packed48 {
+    variant: NonZeroU8,
     data: [u8; 12],
 }
+// SAFETY: pointers are only to data that is Send + Sync: str, Arc<str> and OutOfLine
+unsafe impl Send for Packed48 {}
+unsafe impl Sync for Packed48 {}
+
+impl Packed48 {
+    /// How many bits of virtual addresses the CPU supports, as reported by CPUID.
+    ///
+    /// If it's more than 48, strings might be stored through an extra allocation,
+    /// and a process that creates many of them might be better off with the `Wide` layout.
+    /// The operating system must also enable 5-level paging for addresses to actually be that wide.
+    pub fn address_bits() -> u8 {
+        if __cpuid(0x8000_0000).eax < 0x8000_0008 {
+            return 48;// leaf not supported, so it's an old CPU
+        }
+        (__cpuid(0x8000_0008).eax >> 8) as u8
+    }
+    /// the OutOfLine of a str whose pointer or length needs more than 48 bits
+    #[inline]
+    fn out_of_line_ptr(&self) -> Option<*mut OutOfLine> {
+        if self.variant.get() == OUT_OF_LINE {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&self.data[..8]);
+            // the provenance was exposed by with_pointer()
+            Some(ptr::with_exposed_provenance_mut(usize::from_le_bytes(bytes)))
+        } else {
+            None
+        }
+    }
+    #[inline]
+    fn out_of_line(&self) -> Option<&OutOfLine> {
+        // SAFETY: the OutOfLine lives until drop, and is never written to.
+        self.out_of_line_ptr().map(|pointer| unsafe{ &*pointer })
+    }
+}
+impl Drop for Packed48 {
+    fn drop(&mut self) {
+        if let Some(pointer) = self.out_of_line_ptr() {
+            // SAFETY: the pointer came from OutOfLine::allocate() and is only freed here.
+            unsafe{ OutOfLine::free(pointer) };
+        }
+    }
+}
 
 
   //////////////////
  //Helper methods//
 //////////////////
 
-fn from_parts(variant: u8,  data: [u8; 12]) -> Nbstr {
-    Nbstr{variant: unsafe{ NonZero::new(variant) },  data: data}
+const fn from_parts(variant: u8,  data: [u8; 12]) -> Packed48 {
+    // SAFETY: variants start at 1, as 0 is left for Option.
+    Packed48{variant: unsafe{ NonZeroU8::new_unchecked(variant) },  data: data}
 }
-/// assumes non-stack
-unsafe fn set_ptr(z: &mut Nbstr,  s: *const u8) {
-    let ptr = s as usize;
-    if cfg!(debug_assertions)  &&  ptr > 0x0000_7fff_ffff_ffff_usize
-                               &&  ptr < 0xffff_8000_0000_0000_usize {
-        panic!(MORE_THAN_48_BITS);
-    }
-    z.data[06] = (ptr<<00) as u8;
-    z.data[07] = (ptr>>08) as u8;
-    z.data[08] = (ptr>>16) as u8;
-    z.data[09] = (ptr>>24) as u8;
-    z.data[10] = (ptr>>32) as u8;
-    z.data[11] = (ptr>>40) as u8;
+/// Used by `NbCow::from_static()`.
+/// Cannot be const, because pointers cannot be split into 48 bits at compile time.
+pub fn literal<'a>(s: &'static [u8]) -> NbCowIn<'a, Packed48> {
+    NbCowIn::with_pointer(Packed48::LITERAL, s)
 }
-fn get_ptr(z: &Nbstr) -> *const u8 {
-    if *z.variant > MAX_STACK {
-        let signed : *const isize = unsafe{ mem::transmute(z.data[4..].as_ptr())};
-        let shifted = unsafe{*signed} >> 16;//sign extension
-        shifted as *const u8
-    } else {
-        &z.data as *const u8
+/// Used by `nbstr!()` to store short literals inline at compile time.
+pub const fn inline<'a>(s: &[u8]) -> NbCowIn<'a, Packed48> {
+    let mut data = [0; 12];
+    let mut i = 0;
+    while i < s.len() {
+        data[i] = s[i];
+        i += 1;
     }
+    NbCowIn::from_raw(from_parts(s.len() as u8, data))
 }
-/// assumes non-stack
-unsafe fn set_len(z: &mut Nbstr,  len: usize) {
-    if cfg!(debug_assertions)  &&  len > 0x0000_ffff_ffff_ffff_usize {
-        panic!(MORE_THAN_48_BITS);
+
+impl<'a> NbCowIn<'a, Packed48> {
+    /// Store a `&'static str` without copying it.
+    ///
+    /// Is not `const` for this layout,
+    /// because pointers cannot be split into 48 bits at compile time.
+    pub fn from_static(s: &'static str) -> Self {
+        literal(s.as_bytes())
     }
-    z.data[0] = (len>>00) as u8;
-    z.data[1] = (len>>08) as u8;
-    z.data[2] = (len>>16) as u8;
-    z.data[3] = (len>>24) as u8;
-    z.data[4] = (len>>32) as u8;
-    z.data[5] = (len>>40) as u8;
-}
-fn get_len(z: &Nbstr) -> usize {
-    if *z.variant > MAX_STACK {
-        let location = z.data.as_ptr();
-        let len : *const usize = unsafe{ mem::transmute(location) };
-        unsafe{*len & 0x0000_ffff_ffff_ffff}
-    } else {
-        *z.variant as usize
+    #[doc(hidden)]
+    pub fn from_static_or_inline(s: &'static str) -> Self {
+        if s.is_empty()  ||  s.len() > MAX_STACK as usize {
+            literal(s.as_bytes())
+        } else {
+            inline(s.as_bytes())
+        }
     }
 }
+/// Whether the pointer is unchanged by truncating it to 48 bits and sign-extending it back.
+fn fits_48_bits(s: *const [u8]) -> bool {
+    let ptr = s as *const u8 as usize;
+    ((ptr << 16) as isize >> 16) as usize == ptr  &&  s.len() <= MAX_48BIT
+}
+// A pointer split into bytes loses its provenance, so it's exposed when stored
+//  and recovered with with_exposed_provenance() when read.
+// This is sound with the exposed provenance model, but not with strict provenance.
+/// assumes non-stack and fits_48_bits()
+fn set_ptr(z: &mut Packed48,  s: *const u8) {
+    z.data[6..].copy_from_slice(&s.expose_provenance().to_le_bytes()[..6]);
+}
+/// assumes non-stack and not out of line
+#[inline]
+fn get_ptr(z: &Packed48) -> *const u8 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&z.data[4..]);
+    let shifted = isize::from_le_bytes(bytes) >> 16;//sign extension
+    ptr::with_exposed_provenance(shifted as usize)
+}
+/// assumes non-stack and fits_48_bits()
+fn set_len(z: &mut Packed48,  len: usize) {
+    z.data[..6].copy_from_slice(&len.to_le_bytes()[..6]);
+}
+/// assumes non-stack and not out of line
+#[inline]
+fn get_len(z: &Packed48) -> usize {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&z.data[..8]);
+    usize::from_le_bytes(bytes) & MAX_48BIT
+}
+
 
+impl Protected for Packed48 {
+    const MAX_STACK: u8 = MAX_STACK;
+    const MAX_LENGTH: usize = usize::MAX;
 
-impl Protected for Nbstr {
     fn new(variant: u8) -> Self {
-        from_parts(variant, unsafe{ mem::uninitialized() })
+        from_parts(variant, [0; 12])// inline strings must be zero-padded
     }
-    fn with_pointer(variant: u8,  s: &str) -> Self {
+    fn with_pointer(variant: u8,  s: *const [u8]) -> Self {
+        if !fits_48_bits(s) {// checked in release builds too
+            let pointer = OutOfLine::allocate(variant, s).expose_provenance();
+            let mut z = Self::new(OUT_OF_LINE);
+            z.data[..8].copy_from_slice(&pointer.to_le_bytes());
+            return z;
+        }
         let mut z = Self::new(variant);
-        unsafe{ set_ptr(&mut z,  s.as_ptr()) };
-        unsafe{ set_len(&mut z,  s.len()) };
+        set_ptr(&mut z,  s as *const u8);
+        set_len(&mut z,  s.len());
         return z;
     }
 
+    #[inline]
     fn variant(&self) -> u8 {
-        *self.variant
+        match self.out_of_line() {
+            Some(out_of_line) => out_of_line.variant(),
+            None => self.variant.get(),
+        }
     }
     fn data(&mut self) -> &mut[u8] {
         &mut self.data
     }
+    #[inline]
+    fn get_pointer(&self) -> *const [u8] {
+        match self.out_of_line() {
+            Some(out_of_line) => out_of_line.get_pointer(),
+            None => ptr::slice_from_raw_parts(get_ptr(self),  get_len(self)),
+        }
+    }
+    #[inline]
     fn get_slice(&self) -> &[u8] {
-        unsafe{ slice::from_raw_parts( get_ptr(self), get_len(self) )}
+        if self.variant.get() > MAX_STACK {
+            // SAFETY: the pointer is to 'static or 'a memory, or to a box or Arc owned by self.
+            unsafe{ &*self.get_pointer() }
+        } else {
+            &self.data[..self.variant.get() as usize]
+        }
+    }
+    #[inline]
+    fn inline_words(&self) -> [usize; 2] {
+        let mut padded = [0u8; 16];
+        padded[..12].copy_from_slice(&self.data);
+        // SAFETY: both are plain bytes of the same size.
+        unsafe{ mem::transmute::<[u8; 16], [usize; 2]>(padded) }
     }
 }
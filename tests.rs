@@ -13,22 +13,30 @@
  * limitations under the License.
  */
 
+#![allow(clippy::redundant_static_lifetimes)]
+
 use std::ops::Deref;
 use std::borrow::{Borrow,Cow};
-use std::hash::{Hash,SipHasher};
+use std::hash::{Hash,Hasher};
+use std::collections::hash_map::DefaultHasher;
 use std::fmt::{Display,Debug};
-extern crate nbstr;
-use nbstr::Nbstr;
+use std::sync::Arc;
+#[macro_use] extern crate nbstr;
+extern crate serde_json;
+use nbstr::{Nbstr,NbCow,NbBytes,NbstrBuilder,NbCowIn,NbstrIn};
+use nbstr::layouts::Layout;
+use std::convert::TryFrom;
+#[cfg(feature="std")]
+use nbstr::intern;
 
 /// Catches missing trait impls.
 /// Unfortunately there is no way to prevent aditional public methods or traits.
-
 pub trait NbstrTrait: Sized + Clone + Hash + Eq + Ord + Display + Debug//Debug prepends the str with the way it's stored
- +Default + From<&'static str>+From<Box<str>>+From<String>+From<Cow<'static,str>>
+ +Default + From<&'static str>+From<Box<str>>+From<String>+From<Cow<'static,str>>+From<Arc<str>>
  +Deref<Target=str> + Borrow<str>+Borrow<[u8]> + AsRef<str>+AsRef<[u8]>
- +Into<Box<str>>+Into<String>+Into<Cow<'static,str>>//actually, implement From<Nbstr> for Box<str> and String
+ +Into<Box<str>>+Into<String>+Into<Cow<'static,str>>+Into<Arc<str>>//actually, implement From<Nbstr> for Box<str> and String
 {}
-impl NbstrTrait for Nbstr {}
+impl<L:Layout> NbstrTrait for NbstrIn<L> {}
 
 const A_FEW: &'static str = "\0eéaå𝛼 ∆θ≈π";
 
@@ -90,10 +98,524 @@ fn clone_from() {
     assert_eq!(a, b);
 }
 #[test]
+fn shared() {
+    let a: Arc<str> = Arc::from(A_FEW);
+    let z = Nbstr::from(a.clone());
+    assert_eq!(Arc::strong_count(&a), 2);
+    let c = z.clone();
+    assert_eq!(c.as_ptr(), a.as_ptr());
+    assert_eq!(Arc::strong_count(&a), 3);
+    drop(c);
+    assert_eq!(Arc::strong_count(&a), 2);
+    let b: Arc<str> = z.into();
+    assert!(Arc::ptr_eq(&a, &b));
+    drop(b);
+    assert_eq!(Arc::strong_count(&a), 1);
+    assert_eq!(&*Arc::<str>::from(Nbstr::from("str")), "str");
+    assert_eq!(&*String::from(Nbstr::from(a.clone())), A_FEW);
+    assert_eq!(Arc::strong_count(&a), 1);
+}
+#[test]
+#[cfg(feature="std")]
+fn interned() {
+    use std::cmp::Ordering;
+    let a = Nbstr::intern("interned");
+    let b = Nbstr::intern(&String::from("interned"));
+    assert_eq!(a.as_ptr(), b.as_ptr());
+    assert_eq!(a, b);
+    assert_eq!(a.cmp(&b), Ordering::Equal);
+    assert_eq!(a, Nbstr::from("interned"));
+    assert!(a != Nbstr::intern("Interned"));
+    assert_eq!(a.cmp(&Nbstr::intern("Interned")), "interned".cmp("Interned"));
+    assert_eq!(a.clone().as_ptr(), a.as_ptr());
+    assert_eq!(format!("{:?}", a), "interned: interned");
+    assert_eq!(Nbstr::intern(""), Nbstr::default());
+}
+#[test]
+#[cfg(feature="std")]
+fn intern_collect() {
+    let before = intern::stats();
+    let a = Nbstr::intern("collected");
+    assert!(intern::stats().misses > before.misses);
+    drop(a);
+    assert!(intern::collect() >= 1);
+    intern::seed(vec!["seeded"]);
+    let ptr = Nbstr::intern("seeded").as_ptr();
+    intern::collect();
+    assert_eq!(Nbstr::intern("seeded").as_ptr(), ptr);
+    assert!(intern::stats().seeded >= 1);
+}
+#[test]
+fn borrowed() {
+    let s = String::from(A_FEW);
+    let b = NbCow::borrowed(&s);
+    assert_eq!(b.as_ptr(), s.as_ptr());
+    assert_eq!(b.clone().as_ptr(), s.as_ptr());
+    assert_eq!(format!("{:?}", b), format!("borrowed: {}", A_FEW));
+    match Cow::from(b.clone()) {
+        Cow::Borrowed(c) => assert_eq!(c.as_ptr(), s.as_ptr()),
+        Cow::Owned(_) => panic!("borrowed NbCow was copied"),
+    }
+    let short = NbCow::borrowed(&s[..2]).into_owned();
+    let owned: Nbstr = b.into_static();
+    drop(s);
+    assert_eq!(owned.deref(), A_FEW);
+    assert_eq!(short.deref(), &A_FEW[..2]);
+    assert_eq!(format!("{:?}", short), format!("stack: {}", &A_FEW[..2]));
+    let literal = NbCow::from(A_FEW);
+    assert_eq!(literal.clone().into_static().as_ptr(), A_FEW.as_ptr());
+    assert!(literal.into_owned().as_ptr() != A_FEW.as_ptr());
+}
+// Packed48 cannot split pointers at compile time
+#[cfg(not(all(feature="64as48bit_hack", target_arch="x86_64")))]
+static STATICS: [Nbstr; 4] = [
+    nbstr!("s"),
+    nbstr!("a str that is too long to be stored inline"),
+    nbstr!("con", "cat", "enated"),
+    Nbstr::from_static(A_FEW),
+];
+#[cfg(not(all(feature="64as48bit_hack", target_arch="x86_64")))]
+const CONST: Nbstr = Nbstr::from_static("");
+#[test]
+#[cfg(not(all(feature="64as48bit_hack", target_arch="x86_64")))]
+fn constants() {
+    assert_eq!(format!("{:?}", STATICS[0]), "stack: s");
+    assert_eq!(format!("{:?}", STATICS[1]), "literal: a str that is too long to be stored inline");
+    assert_eq!(STATICS[2].deref(), "concatenated");
+    assert_eq!(STATICS[3].as_ptr(), A_FEW.as_ptr());
+    assert_eq!(STATICS[3].clone(), Nbstr::from(A_FEW));
+    assert_eq!(CONST, Nbstr::default());
+}
+#[test]
+fn macro_expression() {
+    assert_eq!(nbstr!(""), Nbstr::default());
+    assert_eq!(nbstr!("con", "cat", "enated").deref(), "concatenated");
+}
+#[test]
+fn introspection() {
+    use nbstr::Repr;
+    let literal = Nbstr::from(A_FEW);
+    assert_eq!(literal.repr(), Repr::Static);
+    assert_eq!(literal.as_static().map(str::as_ptr), Some(A_FEW.as_ptr()));
+    assert_eq!(literal.heap_size(), 0);
+    assert!(literal.ptr_eq(&literal.clone()));
+    let inline = Nbstr::from_str("abc");
+    assert!(inline.is_inline());
+    assert_eq!(inline.as_static(), None);
+    assert!(!inline.ptr_eq(&inline.clone()));
+    let boxed = Nbstr::from(A_FEW.to_string());
+    assert_eq!(boxed.repr(), Repr::Heap);
+    assert_eq!(boxed.heap_size(), A_FEW.len());
+    assert!(!boxed.ptr_eq(&literal));
+    let shared = Nbstr::from(Arc::<str>::from(A_FEW));
+    assert_eq!(shared.repr(), Repr::Shared);
+    assert!(shared.ptr_eq(&shared.clone()));
+    assert!(shared.heap_size() > A_FEW.len());
+    assert_eq!(NbCow::borrowed(&String::from(A_FEW)).repr(), Repr::Borrowed);
+    assert_eq!(Nbstr::SIZE, std::mem::size_of::<Nbstr>());
+    assert!(Nbstr::from_str(&"a".repeat(Nbstr::MAX_STACK)).is_inline());
+    assert!(!Nbstr::from_str(&"a".repeat(Nbstr::MAX_STACK+1)).is_inline());
+}
+#[test]
+fn leak() {
+    use nbstr::Repr;
+    let boxed = Nbstr::from(A_FEW.to_string());
+    let ptr = boxed.as_ptr();
+    let literal = boxed.into_literal();
+    assert_eq!(literal.repr(), Repr::Static);
+    assert_eq!(literal.as_ptr(), ptr);
+    match Cow::from(literal.clone()) {
+        Cow::Borrowed(s) => assert_eq!(s.as_ptr(), ptr),
+        Cow::Owned(_) => panic!("literal was copied"),
+    }
+    let a = Arc::<str>::from(A_FEW);
+    let shared = Nbstr::from(a.clone()).into_literal();
+    assert_eq!(shared.as_ptr(), a.as_ptr());
+    assert_eq!(Arc::strong_count(&a), 2);
+    assert!(Nbstr::from_str("abc").into_literal().is_inline());
+    let s = String::from(A_FEW);
+    let borrowed = NbCow::borrowed(&s).into_literal();
+    assert_eq!(borrowed.repr(), Repr::Static);
+    assert!(borrowed.as_ptr() != s.as_ptr());
+    drop(s);
+    assert_eq!(&*borrowed, A_FEW);
+
+    assert_eq!(Nbstr::from(A_FEW.to_string()).leak(), A_FEW);
+    assert_eq!(Nbstr::from(A_FEW).leak().as_ptr(), A_FEW.as_ptr());
+    assert_eq!(Nbstr::from_str("abc").leak(), "abc");
+    assert_eq!(Nbstr::default().leak(), "");
+}
+#[test]
+#[cfg(feature="stats")]
+fn stats() {
+    use nbstr::stats;
+    // other tests run in parallel, so counters can only be checked for increases
+    let before = stats::snapshot();
+    let strings = vec![
+        Nbstr::from(A_FEW),
+        Nbstr::from_str("abc"),
+        Nbstr::from(A_FEW.to_string()),
+        Nbstr::from(String::with_capacity(100) + A_FEW),
+    ];
+    let cloned = strings[2].clone();
+    let after = stats::snapshot();
+    assert!(after.created.literal > before.created.literal);
+    assert!(after.created.inline > before.created.inline);
+    assert!(after.created.boxed >= before.created.boxed+3);
+    assert!(after.allocating_clones > before.allocating_clones);
+    assert!(after.string_reallocations > before.string_reallocations);
+    assert!(after.live_boxed_bytes >= 3*A_FEW.len());
+    drop(cloned);
+
+    let histogram = stats::histogram(&strings);
+    assert_eq!(histogram, stats::Histogram{ literal: 1, inline: 1, boxed: 2, ..Default::default() });
+}
+#[test]
+#[cfg(feature="std")]
+fn hashed() {
+    use std::hash::BuildHasher;
+    use nbstr::{HashedNbstr,HashedNbstrMap,PassThroughState};
+    let key = HashedNbstr::from(A_FEW);
+    let copy = HashedNbstr::new(Nbstr::from(A_FEW.to_string()));
+    assert_eq!(key.hash_value(), copy.hash_value());
+    assert_eq!(key, copy);
+    assert!(key != HashedNbstr::from("other"));
+    assert_eq!(PassThroughState.hash_one(&copy), key.hash_value());
+    let mut map = HashedNbstrMap::default();
+    map.insert(key.clone(), 1);
+    map.insert(HashedNbstr::from("other"), 2);
+    assert_eq!(map.get(&copy), Some(&1));
+    assert_eq!(map.get(&HashedNbstr::default()), None);
+    assert_eq!(HashedNbstr::default(), HashedNbstr::from(""));
+    map.insert(HashedNbstr::from(""), 3);
+    assert_eq!(map.get(&HashedNbstr::default()), Some(&3));
+    assert_eq!(format!("{:?}", key), format!("{:?}", Nbstr::from(A_FEW)));
+    assert_eq!(key.into_nbstr().as_ptr(), A_FEW.as_ptr());
+}
+#[test]
+fn builder() {
+    use std::fmt::Write;
+    let mut b = NbstrBuilder::new();
+    assert!(b.is_empty());
+    b.push_str("a");
+    b.push('é');
+    assert_eq!(format!("{:?}", b.finish()), "stack: aé");
+    assert_eq!(NbstrBuilder::default().finish(), Nbstr::default());
+    let mut b = NbstrBuilder::new();
+    for i in 0..20 {
+        write!(b, "{},", i).unwrap();
+    }
+    let expected = (0..20).map(|i| format!("{},", i) ).collect::<String>();
+    assert_eq!(b.as_str(), expected);
+    let z = b.finish();
+    assert_eq!(format!("{:?}", z), format!("boxed: {}", expected));
+    let unwrapped: Box<str> = z.into();
+    assert_eq!(&*unwrapped, expected);
+    // an early allocation isn't kept
+    let mut b = NbstrBuilder::with_capacity(100);
+    b.push_str("short");
+    assert_eq!(format!("{:?}", b.finish()), "stack: short");
+}
+#[test]
+fn concat_and_join() {
+    assert_eq!(format!("{:?}", Nbstr::concat(&["a", "", "bc"])), "stack: abc");
+    assert_eq!(format!("{:?}", Nbstr::concat(&[])), "literal: ");
+    assert_eq!(format!("{:?}", Nbstr::concat(&["", ""])), "literal: ");
+    let long = Nbstr::concat(&[A_FEW, "::", A_FEW]);
+    assert_eq!(format!("{:?}", long), format!("boxed: {}::{}", A_FEW, A_FEW));
+    let unwrapped: String = long.into();
+    assert_eq!(unwrapped.capacity(), unwrapped.len());
+
+    assert_eq!(&*Nbstr::join(&["std", "collections", "hash_map"], "::"), "std::collections::hash_map");
+    assert_eq!(&*Nbstr::join(vec![String::from("a")], "::"), "a");
+    assert_eq!(&*Nbstr::join(Vec::<&str>::new(), "::"), "");
+    assert_eq!(&*Nbstr::join(&["", ""], "/"), "/");
+
+    // uses the inline capacity of the layout, not of Nbstr
+    use nbstr::layouts::{Wide,InlineN};
+    let sixteen = NbstrIn::<Wide>::join(&["std", "collections"], "::");
+    assert_eq!(format!("{:?}", sixteen), "stack: std::collections");
+    let boxed = NbstrIn::<InlineN<4>>::concat(&["a", "bcde"]);
+    assert_eq!(format!("{:?}", boxed), "boxed: abcde");
+    let unwrapped: String = boxed.into();
+    assert_eq!(unwrapped.capacity(), unwrapped.len());
+
+    let literal = Nbstr::from(A_FEW);
+    assert_eq!((literal.clone() + "").as_ptr(), A_FEW.as_ptr());
+    assert_eq!(format!("{:?}", Nbstr::from("a") + "b"), "stack: ab");
+    assert_eq!(&*(literal + "!"), format!("{}!", A_FEW));
+}
+#[test]
+fn substrings() {
+    const PADDED: &'static str = "  a literal that is long enough to not be stored inline \n";
+    let literal = Nbstr::from(PADDED);
+    let trimmed = literal.trim();
+    assert_eq!(&*trimmed, PADDED.trim());
+    assert_eq!(trimmed.as_ptr(), PADDED.trim().as_ptr());
+    assert_eq!(format!("{:?}", literal.slice(2..3)), "literal: a");
+    assert_eq!(format!("{:?}", literal.slice(2..2)), "literal: ");
+    assert_eq!(&*literal.slice(..=3), "  a ");
+    assert_eq!(literal.slice(4..).as_ptr(), PADDED[4..].as_ptr());
+    let (first, second) = literal.split_at(3);
+    assert_eq!((&*first, &*second), PADDED.split_at(3));
+    assert_eq!(&*literal.trim_start().strip_prefix("a ").unwrap(), &PADDED[4..]);
+    assert_eq!(literal.trim_end().strip_suffix("inline").unwrap().len(), PADDED.len()-8);
+    assert_eq!(literal.strip_prefix("a"), None);
+
+    let boxed = Nbstr::from(PADDED.to_string());
+    assert_eq!(format!("{:?}", boxed.slice(2..3)), "stack: a");
+    let copied = boxed.trim();
+    assert_eq!(format!("{:?}", copied), format!("boxed: {}", PADDED.trim()));
+    assert!(copied.as_ptr() != boxed[2..].as_ptr());
+
+    let owned = String::from(PADDED);
+    let borrowed = NbCow::borrowed(&owned);
+    assert_eq!(borrowed.trim().as_ptr(), owned.trim().as_ptr());
+}
+#[test]
+fn splitting() {
+    const GRAMMAR: &'static str = "expression = term { (\"+\" | \"-\") term }\r\nterm = factor\n\n";
+    let literal = Nbstr::from(GRAMMAR);
+    let lines = literal.lines().collect::<Vec<Nbstr>>();
+    assert_eq!(lines.iter().map(|line| &**line ).collect::<Vec<&str>>(), GRAMMAR.lines().collect::<Vec<&str>>());
+    assert_eq!(lines[0].as_ptr(), GRAMMAR.as_ptr());
+    assert_eq!(format!("{:?}", lines[1]), "literal: term = factor");
+    assert_eq!(&*literal.lines().next_back().unwrap(), "");
+    let words = literal.split_whitespace().map(|word| format!("{:?}", word) ).collect::<Vec<_>>();
+    assert_eq!(words[..3], ["literal: expression", "literal: =", "literal: term"]);
+    assert_eq!(&*literal.split_whitespace().next_back().unwrap(), "factor");
+    assert_eq!(literal.split(" = ").count(), 3);
+    let mut two = literal.splitn(2, " = ");
+    assert_eq!(&*two.next().unwrap(), "expression");
+    assert_eq!(two.next().unwrap().as_ptr(), GRAMMAR[13..].as_ptr());
+    assert_eq!(two.next(), None);
+
+    let boxed = Nbstr::from(GRAMMAR.to_string());
+    let mut parts = boxed.splitn(2, " = ");
+    assert_eq!(format!("{:?}", parts.next().unwrap()), "stack: expression");
+    assert_eq!(format!("{:?}", parts.next().unwrap()), format!("boxed: {}", &GRAMMAR[13..]));
+    assert_eq!(format!("{:?}", boxed.split_whitespace().nth(1).unwrap()), "stack: =");
+}
+#[test]
+#[should_panic]
+fn slice_inside_char() {
+    Nbstr::from("é").slice(1..);
+}
+#[test]
+#[should_panic]
+fn slice_end_overflow() {// must panic in release builds too
+    Nbstr::from("str").slice(..=usize::MAX);
+}
+#[test]
+fn bytes() {
+    const INVALID: &'static [u8] = b"\xff not UTF-8, and too long to be stored inline";
+    let literal = NbBytes::from(INVALID);
+    assert_eq!(literal.as_ptr(), INVALID.as_ptr());
+    assert_eq!(format!("{:?}", NbBytes::from(&b"a\x80"[..])), "literal: b\"a\\x80\"");
+    assert_eq!(format!("{:?}", NbBytes::from_slice(b"a\x80")), "stack: b\"a\\x80\"");
+    let boxed = NbBytes::from(INVALID.to_vec().into_boxed_slice());
+    assert_eq!(boxed, literal);
+    assert_eq!(&*boxed.clone(), INVALID);
+    let ptr = boxed.as_ptr();
+    let unwrapped: Box<[u8]> = boxed.into();
+    assert_eq!(unwrapped.as_ptr(), ptr);
+    assert_eq!(Vec::<u8>::from(NbBytes::from(INVALID.to_vec())), INVALID);
+    let error = Nbstr::try_from(literal).unwrap_err();
+    assert_eq!(error.utf8_error().valid_up_to(), 0);
+    assert_eq!(error.into_bytes().as_ptr(), INVALID.as_ptr());
+    let z = Nbstr::from(A_FEW.to_string());
+    let ptr = z.as_ptr();
+    let b = NbBytes::from(z);
+    assert_eq!(&*b, A_FEW.as_bytes());
+    let z = Nbstr::try_from(b).unwrap();
+    assert_eq!(z.as_ptr(), ptr);
+    assert_eq!(NbBytes::default(), NbBytes::from_static(b""));
+}
+#[test]
+#[cfg(feature="std")]
+fn os_str_and_path() {
+    use std::ffi::{OsStr,OsString};
+    use std::path::{Path,PathBuf};
+    use nbstr::{NbOsStr,NbPath};
+    const LONG: &'static str = "/usr/share/doc/nbstr/examples/a_long_path.rs";
+    #[cfg(feature="stats")]
+    let before = nbstr::stats::snapshot().created.literal;
+    let literal = NbPath::from(Path::new(LONG));
+    #[cfg(feature="stats")]
+    assert!(nbstr::stats::snapshot().created.literal > before);
+    assert_eq!(literal.as_os_str().len(), LONG.len());
+    assert_eq!(format!("{:?}", literal), format!("literal: {:?}", LONG));
+    assert_eq!(format!("{:?}", NbPath::from_path(Path::new("/tmp"))), "stack: \"/tmp\"");
+    let buf = PathBuf::from(LONG);
+    let ptr = buf.as_os_str().as_encoded_bytes().as_ptr();
+    let boxed = NbPath::from(buf);
+    assert_eq!(boxed, literal);
+    assert_eq!(boxed.file_name(), Some(OsStr::new("a_long_path.rs")));
+    let unwrapped = PathBuf::from(boxed);
+    assert_eq!(unwrapped.as_os_str().as_encoded_bytes().as_ptr(), ptr);
+    // compare by components
+    assert_eq!(NbPath::from(Nbstr::from("a//b")), NbPath::from(Nbstr::from("a/b")));
+    assert!(NbOsStr::from(Nbstr::from("a//b")) != NbOsStr::from(Nbstr::from("a/b")));
+    let s = NbOsStr::from(OsString::from(A_FEW));
+    assert_eq!(s.to_str(), Some(A_FEW));
+    assert_eq!(OsString::from(s.clone()), OsString::from(A_FEW));
+    let p = NbPath::from(s);
+    assert!(p.ends_with(A_FEW));
+    assert!(std::fs::metadata(&p).is_err());// contains a nul byte
+    assert_eq!(NbOsStr::default().len(), 0);
+}
+#[test]
+fn fast_comparisons() {
+    use std::hash::BuildHasher;
+    use std::collections::hash_map::RandomState;
+    let strs = ["", "a", "a\0", "a\0\0", "ab", "b", "\0", "\u{ff}", "abcdefgh\0", "abcdefghi",
+                "abcdefghijklmno", "abcdefghijklmnop", "abcdefghijklmnopq", A_FEW];
+    let mut nbstrs = Vec::new();
+    for &s in &strs {
+        nbstrs.push((s, Nbstr::from(s)));
+        nbstrs.push((s, Nbstr::from_str(s)));
+        nbstrs.push((s, Nbstr::from(s.to_string().into_boxed_str())));
+    }
+    let state = RandomState::new();
+    for &(ls, ref l) in &nbstrs {
+        assert_eq!(state.hash_one(l), state.hash_one(ls), "{:?}", l);
+        for &(rs, ref r) in &nbstrs {
+            assert_eq!(l == r, ls == rs, "{:?} == {:?}", l, r);
+            assert_eq!(l.cmp(r), ls.cmp(rs), "{:?} cmp {:?}", l, r);
+        }
+    }
+}
+/// Is generic so that every layout is tested regardless of features.
+fn each_representation<L:Layout>() {
+    use nbstr::Repr;
+    let long = "a str that is too long to be stored inline";
+    let inline = NbstrIn::<L>::from_str("abc");
+    assert!(inline.is_inline());
+    assert_eq!(inline.clone().deref(), "abc");
+    let literal = NbstrIn::<L>::from(long);
+    assert_eq!(literal.as_static().map(str::as_ptr), Some(long.as_ptr()));
+    let boxed = NbstrIn::<L>::from(long.to_string());
+    assert_eq!(boxed.repr(), Repr::Heap);
+    assert_eq!(boxed.clone(), boxed);
+    let shared = NbstrIn::<L>::from(Arc::<str>::from(long));
+    assert_eq!(shared.repr(), Repr::Shared);
+    assert_eq!(Arc::<str>::from(shared.clone()).deref(), long);
+    let s = String::from(long);
+    let borrowed = NbCowIn::<L>::borrowed(&s);
+    assert_eq!(borrowed.repr(), Repr::Borrowed);
+    assert_eq!(borrowed.split(" ").map(|part| part.repr() ).last(), Some(Repr::Borrowed));
+    assert_eq!(NbstrIn::<L>::concat(&[&inline, &boxed]).len(), 3+long.len());
+    assert_eq!(NbstrIn::<L>::default(), NbstrIn::<L>::from(""));
+    assert_eq!(NbstrIn::<L>::SIZE, std::mem::size_of::<NbstrIn<L>>());
+    assert_eq!(std::mem::size_of::<Option<NbstrIn<L>>>(), NbstrIn::<L>::SIZE);
+
+    // ownership must be handed back exactly once
+    let upper = long.to_uppercase();
+    let b: Box<str> = long.into();
+    let ptr = b.as_ptr();
+    let mut boxed = NbstrIn::<L>::from(b);
+    boxed.clone_from(&NbstrIn::<L>::from(upper.clone()));// reuses the box
+    assert_eq!((boxed.as_ptr(), boxed.deref()), (ptr, &upper[..]));
+    let b = Box::<str>::from(boxed);
+    assert_eq!(b.as_ptr(), ptr);
+    let arc = Arc::<str>::from(long);
+    let shared = NbstrIn::<L>::from(arc.clone());
+    drop(shared.clone().into_layout::<nbstr::layouts::Wide>());
+    assert_eq!(Arc::strong_count(&arc), 2);
+    drop(shared);
+    assert_eq!(Arc::strong_count(&arc), 1);
+    #[cfg(feature="std")]
+    {
+        let interned = NbstrIn::<L>::intern(long);
+        assert_eq!(interned.repr(), Repr::Interned);
+        assert!(interned.clone().ptr_eq(&interned));
+    }
+}
+#[test]
+fn inline_capacity() {
+    use nbstr::{NbstrN,Repr};
+    use nbstr::layouts::{Wide,InlineN};
+    use std::mem::size_of;
+    let id = "an_identifier_of_27_bytes__";
+    let z = NbstrN::<32>::from_str(id);
+    assert_eq!(z.repr(), Repr::Inline);
+    assert_eq!(NbstrN::<32>::MAX_STACK, 32);
+    assert_eq!(size_of::<NbstrN<32>>(), 33);
+    assert_eq!(size_of::<Option<NbstrN<32>>>(), 33);
+    assert_eq!(size_of::<NbstrN<4>>(), size_of::<NbstrIn<Wide>>());
+    // bytes after the first two words must be compared too
+    let other = NbstrN::<32>::from_str("an_identifier_of_27_bytes_!");
+    assert!(z != other  &&  z > other);
+    assert_eq!(z, NbstrN::<32>::from(id));
+    let mut hashers = (DefaultHasher::new(), DefaultHasher::new());
+    z.hash(&mut hashers.0);
+    id.hash(&mut hashers.1);
+    assert_eq!(hashers.0.finish(), hashers.1.finish());
+    // is moved into a box when converted to a layout with less room
+    let wide = NbstrIn::<Wide>::from(z.clone());
+    assert_eq!(wide.repr(), Repr::Heap);
+    assert_eq!(NbstrN::<32>::from(wide).repr(), Repr::Heap);// keeps the box
+    assert_eq!(z.clone().into_layout::<InlineN<8>>().repr(), Repr::Heap);
+    const ID: NbstrN<32> = NbstrN::<32>::from_static_or_inline("an_identifier_of_27_bytes__");
+    assert_eq!(ID, z);
+    assert!(ID.is_inline());
+    assert_eq!(NbstrN::<32>::concat(&["an_identifier", "_of_27_bytes__"]).repr(), Repr::Inline);
+}
+#[test]
+fn layouts() {
+    use nbstr::Repr;
+    use nbstr::layouts::{Wide,NoGiants};
+    use std::mem::size_of;
+    each_representation::<Wide>();
+    each_representation::<NoGiants>();
+    #[cfg(all(target_arch="x86_64", not(miri)))]// Packed48 uses exposed provenance
+    each_representation::<nbstr::layouts::Packed48>();
+    each_representation::<nbstr::layouts::InlineN<32>>();
+    each_representation::<nbstr::layouts::InlineN<4>>();
+    #[cfg(target_pointer_width="64")]
+    {
+        use nbstr::layouts::Len32;
+        each_representation::<Len32>();
+        assert_eq!(size_of::<NbstrIn<Len32>>(), 13);
+        const LITERAL: NbstrIn<Len32> = NbstrIn::<Len32>::from_static(A_FEW);
+        assert_eq!(LITERAL.as_static().map(str::as_ptr), Some(A_FEW.as_ptr()));
+        assert_eq!(NbstrIn::<Len32>::from(NbstrIn::<NoGiants>::from(LITERAL)).as_ptr(), A_FEW.as_ptr());
+    }
+    assert_eq!(NbstrIn::<Wide>::MAX_STACK, 2*size_of::<usize>());
+    assert_eq!(size_of::<NbstrIn<NoGiants>>(), size_of::<&str>());
+
+    let long = "a str that is too long to be stored inline";
+    let wide = NbstrIn::<Wide>::from(long.to_string());
+    let ptr = wide.as_ptr();
+    let no_giants = NbstrIn::<NoGiants>::from(wide);
+    assert_eq!(no_giants.repr(), Repr::Heap);
+    assert_eq!(no_giants.as_ptr(), ptr);
+    let back: NbstrIn<Wide> = no_giants.into();
+    assert_eq!(back.as_ptr(), ptr);
+    let literal = NbstrIn::<NoGiants>::from_static(long).into_layout::<Wide>();
+    assert_eq!(literal.as_static(), Some(long));
+    // is moved into a box if it doesn't fit
+    let full = "a".repeat(NbstrIn::<Wide>::MAX_STACK);
+    let moved = NbstrIn::<Wide>::from_str(&full).into_layout::<NoGiants>();
+    assert_eq!(moved.deref(), full);
+    assert_eq!(moved.repr(), Repr::Heap);
+    #[cfg(all(target_arch="x86_64", not(miri)))]
+    {
+        use nbstr::layouts::Packed48;
+        let shared = NbstrIn::<Packed48>::from(Arc::<str>::from(long));
+        let shared = NbstrIn::<NoGiants>::from(shared);
+        assert_eq!(shared.repr(), Repr::Shared);
+        assert_eq!(NbstrIn::<Wide>::from(NbstrIn::<Packed48>::from(shared)).deref(), long);
+    }
+}
+#[test]
 fn simple_derefs() {
-    assert_eq!(Nbstr::from("abc").hash(&mut SipHasher::new_with_keys(0, 1)),
-                           "abc".hash(&mut SipHasher::new_with_keys(0, 1))
-               );
+    fn hash<H:Hash+?Sized>(v: &H) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        v.hash(&mut hasher);
+        hasher.finish()
+    }
+    assert_eq!(hash(&Nbstr::from("abc")), hash("abc"));
     assert_eq!(format!("a{}", Nbstr::from("bc")), format!("a{}", "bc"));
     assert_eq!(Nbstr::from("abc")==Nbstr::from_str("abc"),  "abc"=="abc");
     assert_eq!(Nbstr::from("abc")==Nbstr::from_str("aBc"),  "abc"=="aBc");
@@ -104,9 +626,27 @@ fn simple_derefs() {
 #[allow(non_snake_case)]
 fn is_NonZero() {
     use std::mem::{size_of,align_of};
-    if cfg!(feature="unstable") {
-        assert_eq!(size_of::<Option<Nbstr>>(), size_of::<Nbstr>());
+    assert_eq!(size_of::<Option<Nbstr>>(), size_of::<Nbstr>());
+    if cfg!(all(feature="64as48bit_hack", target_arch="x86_64")) {
+        assert_eq!(size_of::<Nbstr>(), 13);
+    } else {
+        assert_eq!(size_of::<Nbstr>(), size_of::<&str>());
     }
     assert!( size_of::<Nbstr>() < size_of::<Cow<'static, str>>() );
-    assert!( size_of::<Nbstr>() % align_of::<Nbstr>() == 0 );
+    assert!( size_of::<Nbstr>().is_multiple_of(align_of::<Nbstr>()) );
+}
+#[test]
+#[cfg(feature="serde")]
+fn serde() {
+    const LONG: &'static str = "\"a str that is too long to be stored inline\"";
+    let json = format!("[\"short\",{}]", LONG);
+    let v: Vec<Nbstr> = serde_json::from_str(&json).unwrap();
+    assert_eq!(format!("{:?}", v[0]), "stack: short");
+    assert_eq!(format!("{:?}", v[1]), format!("boxed: {}", &LONG[1..LONG.len()-1]));
+    assert_eq!(serde_json::to_string(&v).unwrap(), json);
+    let mut de = serde_json::Deserializer::from_str(LONG);
+    let literal = nbstr::deserialize_literal(&mut de).unwrap();
+    assert_eq!(literal.as_ptr(), LONG[1..].as_ptr());
+    let mut de = serde_json::Deserializer::from_str("\"esc\\naped\"");
+    assert_eq!(nbstr::deserialize_literal(&mut de).unwrap(), Nbstr::from("esc\naped"));
 }